@@ -35,6 +35,8 @@ config :philomena,
   tag_file_root: System.fetch_env!("TAG_FILE_ROOT"),
   hide_version: System.get_env("HIDE_VERSION", "false"),
   site_domains: System.fetch_env!("SITE_DOMAINS"),
+  external_links_new_tab: System.get_env("EXTERNAL_LINKS_NEW_TAB", "false") in ["true", "1"],
+  link_blocklist: System.get_env("LINK_BLOCKLIST", ""),
   tag_url_root: System.fetch_env!("TAG_URL_ROOT"),
   redis_host: System.get_env("REDIS_HOST", "localhost"),
   proxy_host: System.get_env("PROXY_HOST"),
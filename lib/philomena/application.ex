@@ -7,6 +7,7 @@ defmodule Philomena.Application do
 
   def start(_type, _args) do
     configure_logging()
+    Philomena.Markdown.put_link_policy()
 
     # List all child processes to be supervised
     children = [
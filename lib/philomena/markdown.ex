@@ -1,12 +1,195 @@
 defmodule Philomena.Markdown do
   @markdown_chars ~r/[\*_\[\]\(\)\^`\%\\~<>#\|]/
 
+  @default_sanitize_policy [
+    tags:
+      ~w(a abbr b blockquote br code dd del details div dl dt em h1 h2 h3 h4 h5 h6 hr i img ins
+         kbd li mark ol p pre s small span strong sub summary sup table tbody td th thead tr u ul),
+    attributes: %{
+      "a" => ~w(href title),
+      "img" => ~w(src alt title width height),
+      "td" => ~w(align colspan rowspan),
+      "th" => ~w(align colspan rowspan),
+      "ol" => ~w(start)
+    },
+    generic_attributes: ~w(class title),
+    url_schemes: ~w(http https mailto)
+  ]
+
+  @default_limits [
+    max_input_bytes: 300_000,
+    max_output_bytes: 4_000_000,
+    max_depth: 64,
+    max_table_cells: 10_000
+  ]
+
+  @default_diff_options [
+    context_lines: 3,
+    algorithm: :patience,
+    timeout_ms: 50
+  ]
+
   @doc """
   Converts user-input Markdown to HTML, with the specified map of image
   replacements (which converts ">>1234p" syntax to an embedded image).
+
+  ## Options
+
+    * `:profile` - the feature set to render with, one of `:comment`
+      (default), `:signature`, `:user_description`, `:tag_description`,
+      `:static_page` or `:title`. Signatures and titles render without
+      images (including image mentions), tables, headings or description
+      lists; user descriptions render without headings. Raw HTML is escaped
+      under every profile; trusted static page sources go through
+      `to_html_unsafe/2` instead.
+    * `:highlight` - list of languages (such as `["elixir", "rust"]`) whose
+      fenced code blocks are syntax highlighted with class-based `<span>`
+      tokens. Other code blocks, and very large ones, render as usual.
+    * `:sourcepos` - when `true`, block elements carry a `data-sourcepos`
+      attribute (`"line:col-line:col"`) mapping them back to the source, for
+      quote-and-reply (default `false`)
+  """
+  @spec to_html(String.t(), %{String.t() => String.t()}, keyword()) :: String.t()
+  def to_html(text, replacements, opts \\ [])
+
+  def to_html(text, replacements, []), do: Philomena.Native.markdown_to_html(text, replacements)
+
+  def to_html(text, replacements, opts),
+    do: Philomena.Native.markdown_to_html_with_options(text, replacements, html_options(opts))
+
+  @doc """
+  Sets how rendered links to other sites are treated, from the
+  `:external_links_new_tab` and `:link_blocklist` application settings.
+  Off-site links always get `rel="nofollow ugc noopener"`; with
+  `:external_links_new_tab` they also open in a new tab, and links to hosts
+  in the comma-separated `:link_blocklist` (where `*.example.com` matches
+  every subdomain) are removed, leaving their text.
+  """
+  @spec put_link_policy() :: :ok
+  def put_link_policy do
+    Philomena.Native.markdown_set_link_policy(%{
+      new_tab: Application.get_env(:philomena, :external_links_new_tab, false),
+      blocklist: String.split(Application.get_env(:philomena, :link_blocklist, ""), ",")
+    })
+  end
+
+  @doc """
+  Converts user-input Markdown to HTML like `to_html/2`, but refuses to
+  render documents which would be too expensive. Returns `{:ok, html}`, or
+  `{:error, reason}` where reason is one of `:input_too_large`,
+  `:output_too_large`, `:nesting_too_deep` or `:too_many_table_cells`.
+
+  ## Limits
+
+    * `:max_input_bytes` - largest source accepted (default 300 000)
+    * `:max_output_bytes` - largest HTML produced (default 4 000 000)
+    * `:max_depth` - deepest nesting of blocks and inlines (default 64)
+    * `:max_table_cells` - most table cells in the document (default 10 000)
+
+  A limit of `0` disables that check.
+  """
+  @spec to_html_limited(String.t(), %{String.t() => String.t()}, keyword()) ::
+          {:ok, String.t()} | {:error, atom()}
+  def to_html_limited(text, replacements, limits \\ []) do
+    limits = Map.new(Keyword.merge(@default_limits, limits))
+
+    Philomena.Native.markdown_to_html_limited(text, replacements, limits)
+  end
+
+  @doc """
+  Converts user-input Markdown to HTML like `to_html/2`, also linking
+  `@username` mentions. `users` maps each username which may be mentioned to
+  its profile URL; only those names are linked, matching case-insensitively
+  and preferring the longest name. Mentions in code, links and escapes are
+  ignored.
+
+  Returns `{html, mentioned}`, where `mentioned` lists the usernames (as keys
+  of `users`) in order of first mention.
+  """
+  @spec to_html_with_users(String.t(), %{String.t() => String.t()}, %{
+          String.t() => String.t()
+        }) :: {String.t(), [String.t()]}
+  def to_html_with_users(text, replacements, users),
+    do: Philomena.Native.markdown_to_html_with_users(text, replacements, users)
+
+  @doc """
+  Converts user-input Markdown to HTML like `to_html/2`, also turning each
+  `{{tag name}}` into a link to the tag's page.
+
+  `states` is `nil`, or a map from tag name (as returned by `extract_tags/1`)
+  to `:exists`, `:aliased` or `:missing`; links then carry the class
+  `tag-link`, plus `tag-link--aliased` or `tag-link--missing`. Tags missing
+  from the map are treated as existing.
+
+  Returns `{html, tags}` with the referenced tag names.
+  """
+  @spec to_html_with_tags(String.t(), %{String.t() => String.t()}, %{
+          String.t() => :exists | :aliased | :missing
+        } | nil) :: {String.t(), [String.t()]}
+  def to_html_with_tags(text, replacements, states \\ nil),
+    do: Philomena.Native.markdown_to_html_with_tags(text, replacements, states)
+
+  @doc """
+  Converts user-input Markdown to HTML, generating the markup for image
+  mentions from a list of image embeds instead of a map of prebuilt HTML.
+  Mentions of images not in the list are left as text.
+
+  Each embed is a map with:
+
+    * `:id` - the image ID
+    * `:tag_ids` - the IDs of the image's tags
+    * `:tag_aliases` - the names of the image's tags, each followed by the
+      names of its aliases
+    * `:score`, `:faves`, `:upvotes`, `:downvotes`, `:comment_count` - the
+      image's counts
+    * `:created_at` - the upload time, in ISO 8601
+    * `:sources` - the image's source URLs
+    * `:uris` - map from version name to the URL of that version
+    * `:width`, `:height`, `:aspect_ratio` - the image dimensions, with the
+      aspect ratio as a float
+    * `:thumbnails` - map from the version asked for (`"thumb_small"`,
+      `"small"`, `"medium"`) to a map of the version `:size` actually shown
+      and its `:url`
+    * `:mime_type` - the image MIME type
+    * `:title` - the alt text, used as the link title
+    * `:filter_path` - path of the viewer's current filter
+    * `:blocked_path` - path of the image shown in place of filtered images
+    * `:status` - one of `:visible`, `:merged`, `:deleted` or `:pending`
+
+  `>>1234t`, `>>1234s` and `>>1234p` embed visible images with the same
+  markup as the `_image_target.html` template, leaving the client to show the
+  image or the filter placeholder. Other mentions become links noting the
+  image's status, except for pending images, which are not linked.
+  """
+  @spec to_html_with_embeds(String.t(), [map()]) :: String.t()
+  def to_html_with_embeds(text, embeds),
+    do: Philomena.Native.markdown_to_html_with_embeds(text, embeds)
+
+  @doc """
+  Generates the image mention replacement map for `mentions` (a list of
+  `{id, suffix}` tuples) from a list of image embeds, as described in
+  `to_html_with_embeds/2`. The map can be passed to any function taking
+  replacements.
   """
-  @spec to_html(String.t(), %{String.t() => String.t()}) :: String.t()
-  def to_html(text, replacements), do: Philomena.Native.markdown_to_html(text, replacements)
+  @spec embed_replacements([{integer(), String.t()}], [map()]) :: %{String.t() => String.t()}
+  def embed_replacements(mentions, embeds),
+    do: Philomena.Native.markdown_embed_replacements(mentions, embeds)
+
+  @doc """
+  Converts a list of user-input Markdown documents to HTML in one call,
+  rendering them in parallel. Returns the HTML in the same order as the input.
+
+  Takes either a list of `{text, replacements}` tuples, or a list of texts
+  and one replacement map shared by all of them. A document which fails to
+  render comes back as its escaped source rather than failing the batch.
+  """
+  @spec to_html_batch([{String.t(), %{String.t() => String.t()}}]) :: [String.t()]
+  def to_html_batch(items),
+    do: Philomena.Native.markdown_to_html_batch(items)
+
+  @spec to_html_batch([String.t()], %{String.t() => String.t()}) :: [String.t()]
+  def to_html_batch(texts, replacements),
+    do: Philomena.Native.markdown_to_html_batch_shared(texts, replacements)
 
   @doc """
   Converts trusted-input Markdown to HTML, with the specified map of image
@@ -18,15 +201,271 @@ defmodule Philomena.Markdown do
     do: Philomena.Native.markdown_to_html_unsafe(text, replacements)
 
   @doc """
-  Renders a line-by-line diff between two Markdown sources as an HTML table.
+  Converts semi-trusted Markdown to HTML, with the specified map of image
+  replacements. Raw HTML is allowed, but the output is cleaned against an
+  allowlist: anything not permitted is removed, `<script>` and `<style>`
+  together with their contents. Kept `<img src>` URLs go through the image
+  proxy, every link gets `rel="nofollow ugc noopener"`, and links to hosts on
+  the configured blocklist lose their `href`.
+
+  ## Policy
+
+    * `:tags` - allowed element names
+    * `:attributes` - map from element name to its allowed attributes
+    * `:generic_attributes` - attributes allowed on every element
+    * `:url_schemes` - allowed schemes for absolute URLs; relative URLs are
+      always allowed
+
+  Each key defaults to a set suitable for site notices and descriptions.
+  """
+  @spec to_html_sanitized(String.t(), %{String.t() => String.t()}, keyword()) :: String.t()
+  def to_html_sanitized(text, replacements, policy \\ []) do
+    policy = Map.new(Keyword.merge(@default_sanitize_policy, policy))
+
+    Philomena.Native.markdown_to_html_sanitized(text, replacements, policy)
+  end
+
+  @doc """
+  Converts trusted-input Markdown to HTML like `to_html_unsafe/2`, giving
+  every heading a slugged `id` anchor (repeated headings get a numeric
+  suffix). Also returns the table of contents as a nested list of maps with
+  `:level`, `:title`, `:anchor` and `:children`.
+  """
+  @spec to_html_unsafe_with_toc(String.t(), %{String.t() => String.t()}) ::
+          {String.t(), [map()]}
+  def to_html_unsafe_with_toc(text, replacements),
+    do: Philomena.Native.markdown_to_html_unsafe_with_toc(text, replacements)
+
+  @doc """
+  Converts user-input Markdown to HTML like `to_html/2`, then cuts the
+  result down to an excerpt. Every open tag is closed and entities are never
+  split, so spoilers, tables and links stay well-formed.
+
+  ## Options
+
+    * `:max_chars` - maximum number of visible characters (0 for no limit)
+    * `:max_blocks` - maximum number of top-level blocks (0 for no limit)
+    * `:drop_spoilers` - remove spoilered content instead of keeping it
+      inside its spoiler wrapper (default `false`)
+
+  Returns the excerpt HTML and whether anything was cut.
+  """
+  @spec to_html_excerpt(String.t(), %{String.t() => String.t()}, keyword()) ::
+          {String.t(), boolean()}
+  def to_html_excerpt(text, replacements, opts \\ []) do
+    excerpt = %{
+      max_chars: Keyword.get(opts, :max_chars, 0),
+      max_blocks: Keyword.get(opts, :max_blocks, 0),
+      drop_spoilers: Keyword.get(opts, :drop_spoilers, false)
+    }
+
+    Philomena.Native.markdown_to_html_excerpt(text, replacements, excerpt)
+  end
+
+  @doc """
+  Converts user-input Markdown to readable plain text, following the same
+  parse rules as `to_html/2`. Spoilers are replaced with a placeholder,
+  links become "text (url)", images their alt text (or URL), and table
+  cells are separated by tabs. Intended for emails, search indexing and
+  OpenGraph descriptions; the result is not HTML-escaped.
+  """
+  @spec to_text(String.t()) :: String.t()
+  def to_text(text), do: Philomena.Native.markdown_to_text(text)
+
+  @doc """
+  Parses user-input Markdown with the same rules as `to_html/2` and returns
+  the document tree. Each node is a map with `:kind` (such as `:paragraph`,
+  `:link` or `:spoiler`), `:attrs` (a keyword list of node data, such as
+  `:url` for links), `:sourcepos` (`{{start_line, start_col}, {end_line, end_col}}`)
+  and `:children`.
+
+  Returns `{:ok, tree}`, or `{:error, :nesting_too_deep}` for documents
+  nested more than 128 levels deep.
+  """
+  @spec to_ast(String.t()) :: {:ok, map()} | {:error, :nesting_too_deep}
+  def to_ast(text), do: Philomena.Native.markdown_to_ast(text)
+
+  @doc """
+  Returns every image mention (">>1234", ">>1234p", ">>1234t", ">>1234s") in
+  user-input Markdown, in document order. Mentions inside code spans, code
+  blocks and escaped text are skipped, as `to_html/2` skips them. The match is
+  otherwise looser than the renderer's: it ignores the characters around a
+  mention, so `a>>1` and `>>1pz` are reported (as `1` and `1p`), as are
+  mentions written with entities (`&gt;&gt;1`). Use the result to decide
+  which images to load, not to predict which mentions will be embedded.
+  Each mention is a map with
+  `:id`, `:suffix` (`""`, `"p"`, `"t"` or `"s"`) and `:position`
+  (`{line, column}`).
+  """
+  @spec extract_mentions(String.t()) :: [
+          %{id: integer(), suffix: String.t(), position: {integer(), integer()}}
+        ]
+  def extract_mentions(text), do: Philomena.Native.markdown_extract_mentions(text)
+
+  @doc """
+  Returns every outgoing URL in user-input Markdown: inline and reference
+  links, autolinks and image sources, in document order. Each entry is a map
+  with `:kind` (`:link`, `:image` or `:autolink`), `:url` as written,
+  `:rewritten` as the renderer will emit it (relativized for site domains,
+  proxied for images) and `:position` (`{line, column}`).
+  """
+  @spec extract_links(String.t()) :: [map()]
+  def extract_links(text), do: Philomena.Native.markdown_extract_links(text)
+
+  @doc """
+  Returns the names of the tags referenced with `{{tag name}}` syntax in
+  user-input Markdown, lowercased and with whitespace collapsed, in order of
+  first reference.
+  """
+  @spec extract_tags(String.t()) :: [String.t()]
+  def extract_tags(text), do: Philomena.Native.markdown_extract_tags(text)
+
+  @doc """
+  Checks user-input Markdown for constructs which the renderer tolerates but
+  which are almost certainly mistakes: spoilers that are never closed, links
+  with no text, and tables whose delimiter row does not match the header.
+  When `existing_images` is a list of image IDs, mentions of any other image
+  are reported too.
+
+  Each warning is a map with `:kind`, `:start` and `:end` (inclusive
+  `{line, column}` positions) and a human-readable `:message`.
+  """
+  @spec lint(String.t(), [integer()] | nil) :: [map()]
+  def lint(text, existing_images \\ nil),
+    do: Philomena.Native.markdown_lint(text, existing_images)
+
+  @doc """
+  Builds the Markdown for a quote-reply to `text`, attributed to `author` with
+  a link to the quoted post. Each line gets a proper `> ` quote marker (so
+  greentext survives), lines that would end up nested more than `max_depth`
+  quotes deep are dropped, and image embeds such as `>>1234p` become plain
+  `>>1234` links so they are not embedded again.
+  """
+  @spec quote_reply(String.t(), String.t(), String.t(), non_neg_integer()) :: String.t()
+  def quote_reply(text, author, link, max_depth \\ 2),
+    do: Philomena.Native.markdown_quote(text, author, link, max_depth)
+
+  @doc """
+  Rewrites user-input Markdown in a canonical form: one list marker style,
+  consistently escaped special characters, `\\n` line endings and no trailing
+  whitespace. The result always renders to the same HTML as the original;
+  when the canonical form would not, only line endings and trailing
+  whitespace are cleaned up, or the text is returned unchanged.
+  """
+  @spec format(String.t()) :: String.t()
+  def format(text),
+    do: Philomena.Native.markdown_format(text)
+
+  @doc """
+  Renders a diff between two Markdown sources as HTML. By default this is a
+  line-by-line HTML table.
   Each line becomes a row with old/new line-number gutters, changed lines get
   word-level `<del class="diff__hl">` / `<ins class="diff__hl">` highlights,
   and long unchanged runs collapse into "N unchanged lines" separator rows.
   The source text is HTML-escaped during rendering, so the output is safe.
+
+  Each separator row is in its own `<tbody class="diff__gap">`, with
+  `data-old-start` and `data-new-start` attributes giving the first collapsed
+  line numbers and `data-count` the number of lines. It is followed by a
+  `<tbody class="diff__hidden hidden">` holding the collapsed rows, which can
+  be shown to expand the gap in place.
+
+  `mode` is `:unified` (the default) for a single text column, or `:split`
+  for old and new side by side, with each edited line next to its
+  replacement.
+
+  `:rendered` compares the rendered documents instead of the source: both
+  revisions are rendered as user content (without resolving image mentions)
+  and the result is returned with removed blocks wrapped in
+  `<del class="diff__block">` and added blocks in `<ins class="diff__block">`,
+  inside a `<div class="diff diff--rendered">`.
+
+  `options` may set:
+
+    * `:context_lines` - unchanged lines shown around each change (default 3)
+    * `:algorithm` - `:patience` (the default), `:myers` or `:lcs`
+    * `:timeout_ms` - time allowed before settling for a coarser diff
+      (default 50)
   """
-  @spec to_html_diff(String.t(), String.t()) :: String.t()
-  def to_html_diff(old, new),
-    do: Philomena.Native.markdown_diff_to_html(old, new)
+  @spec to_html_diff(String.t(), String.t(), :unified | :split | :rendered, keyword()) ::
+          String.t()
+  def to_html_diff(old, new, mode \\ :unified, options \\ []) do
+    options = Map.new(Keyword.merge(@default_diff_options, options))
+
+    Philomena.Native.markdown_diff_to_html(old, new, mode, options)
+  end
+
+  @doc """
+  Diffs two Markdown sources line by line and returns the hunks shown by
+  `to_html_diff/4` as data, in order: each changed run of lines, and the
+  unchanged lines of context around each change. Unchanged lines further away
+  are left out, so a gap shows as a jump in the line ranges. `options` are as
+  for `to_html_diff/4`.
+
+  Each hunk is a map with:
+
+    * `:op` - `:equal`, `:delete`, `:insert` or `:replace`
+    * `:old_range`, `:new_range` - `{start, stop}` line ranges counted from
+      0, with `stop` excluded
+    * `:lines` - the old lines, then the new lines, without terminators
+      (unchanged lines appear once)
+    * `:inline_spans` - for each of `:lines`, the `{start, stop}` byte
+      ranges of the changed text
+  """
+  @spec diff_hunks(String.t(), String.t(), keyword()) :: [map()]
+  def diff_hunks(old, new, options \\ []) do
+    options = Map.new(Keyword.merge(@default_diff_options, options))
+
+    Philomena.Native.markdown_diff_hunks(old, new, options)
+  end
+
+  @doc """
+  Counts what changed between two Markdown sources, without building a diff:
+  `:lines_added`, `:lines_removed`, `:chars_added` and `:chars_removed`.
+  Characters are counted within edited lines, not counting line endings, so
+  fixing a typo counts only the characters that changed. `options` are as
+  for `to_html_diff/4`, except `:context_lines`, which has no effect here;
+  `:timeout_ms` bounds the whole count, and edited lines reached after it has
+  passed count all of their characters.
+  """
+  @spec diff_stats(String.t(), String.t(), keyword()) :: %{
+          lines_added: non_neg_integer(),
+          lines_removed: non_neg_integer(),
+          chars_added: non_neg_integer(),
+          chars_removed: non_neg_integer()
+        }
+  def diff_stats(old, new, options \\ []) do
+    options = Map.new(Keyword.merge(@default_diff_options, options))
+
+    Philomena.Native.markdown_diff_stats(old, new, options)
+  end
+
+  @doc """
+  Merges two concurrent edits, `ours` and `theirs`, of the Markdown source
+  `base`. Line endings are normalized to `\\n` first.
+
+  Returns `{:ok, merged}` when the edits change different lines, or made the
+  same change. Otherwise returns `{:error, conflicts}`, where each conflict
+  is a map with the `:base`, `:ours` and `:theirs` line ranges involved, as
+  `{start, stop}` tuples counted from 0 with `stop` excluded, and the text
+  each revision has for those lines. Edits to adjacent lines conflict.
+  """
+  @spec merge(String.t(), String.t(), String.t()) :: {:ok, String.t()} | {:error, [map()]}
+  def merge(base, ours, theirs),
+    do: Philomena.Native.markdown_merge(base, ours, theirs)
+
+  @doc """
+  Converts legacy Textile markup to Markdown which renders the same way.
+  Text that was literal in Textile is escaped so it stays literal.
+
+  Returns a map with the converted `:markdown` and a list of `:unconverted`
+  constructs (such as unbalanced spoiler or quote tags), each a map with the
+  `:line` it appeared on, its `:text` and a `:reason`. Stray tags are kept in
+  the output as escaped text; a quote which is never closed runs to the end.
+  """
+  @spec from_textile(String.t()) :: %{markdown: String.t(), unconverted: [map()]}
+  def from_textile(text),
+    do: Philomena.Native.textile_to_markdown(text)
 
   @doc """
   Escapes special characters in text which is to be rendered as Markdown.
@@ -38,4 +477,12 @@ defmodule Philomena.Markdown do
       "\\#{m}"
     end)
   end
+
+  defp html_options(opts) do
+    %{
+      profile: Keyword.get(opts, :profile, :comment),
+      highlight: Keyword.get(opts, :highlight, []),
+      sourcepos: Keyword.get(opts, :sourcepos, false)
+    }
+  end
 end
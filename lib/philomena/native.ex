@@ -6,11 +6,157 @@ defmodule Philomena.Native do
   @spec markdown_to_html(String.t(), %{String.t() => String.t()}) :: String.t()
   def markdown_to_html(_text, _replacements), do: :erlang.nif_error(:nif_not_loaded)
 
+  @spec markdown_set_link_policy(map()) :: :ok
+  def markdown_set_link_policy(_policy), do: :erlang.nif_error(:nif_not_loaded)
+
+  @spec markdown_to_html_batch([{String.t(), %{String.t() => String.t()}}]) :: [String.t()]
+  def markdown_to_html_batch(_items), do: :erlang.nif_error(:nif_not_loaded)
+
+  @spec markdown_to_html_batch_shared([String.t()], %{String.t() => String.t()}) :: [String.t()]
+  def markdown_to_html_batch_shared(_texts, _replacements),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @spec markdown_to_html_with_options(String.t(), %{String.t() => String.t()}, map()) ::
+          String.t()
+  def markdown_to_html_with_options(_text, _replacements, _options),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @spec markdown_to_html_limited(String.t(), %{String.t() => String.t()}, map()) ::
+          {:ok, String.t()} | {:error, atom()}
+  def markdown_to_html_limited(_text, _replacements, _limits),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @spec markdown_to_html_with_users(
+          String.t(),
+          %{String.t() => String.t()},
+          %{String.t() => String.t()}
+        ) :: {String.t(), [String.t()]}
+  def markdown_to_html_with_users(_text, _replacements, _users),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @spec markdown_to_html_with_tags(String.t(), %{String.t() => String.t()}, map() | nil) ::
+          {String.t(), [String.t()]}
+  def markdown_to_html_with_tags(_text, _replacements, _states),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @spec markdown_to_html_with_embeds(String.t(), [map()]) :: String.t()
+  def markdown_to_html_with_embeds(_text, _embeds), do: :erlang.nif_error(:nif_not_loaded)
+
+  @spec markdown_embed_replacements([{integer(), String.t()}], [map()]) ::
+          %{String.t() => String.t()}
+  def markdown_embed_replacements(_mentions, _embeds), do: :erlang.nif_error(:nif_not_loaded)
+
   @spec markdown_to_html_unsafe(String.t(), %{String.t() => String.t()}) :: String.t()
   def markdown_to_html_unsafe(_text, _replacements), do: :erlang.nif_error(:nif_not_loaded)
 
-  @spec markdown_diff_to_html(String.t(), String.t()) :: String.t()
-  def markdown_diff_to_html(_old, _new), do: :erlang.nif_error(:nif_not_loaded)
+  @spec markdown_to_html_sanitized(String.t(), %{String.t() => String.t()}, map()) ::
+          String.t()
+  def markdown_to_html_sanitized(_text, _replacements, _policy),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @spec markdown_to_html_unsafe_with_toc(String.t(), %{String.t() => String.t()}) ::
+          {String.t(), [map()]}
+  def markdown_to_html_unsafe_with_toc(_text, _replacements),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @spec markdown_to_html_excerpt(String.t(), %{String.t() => String.t()}, %{
+          max_chars: non_neg_integer(),
+          max_blocks: non_neg_integer(),
+          drop_spoilers: boolean()
+        }) :: {String.t(), boolean()}
+  def markdown_to_html_excerpt(_text, _replacements, _excerpt),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @spec markdown_to_text(String.t()) :: String.t()
+  def markdown_to_text(_text), do: :erlang.nif_error(:nif_not_loaded)
+
+  @spec markdown_to_ast(String.t()) :: {:ok, map()} | {:error, :nesting_too_deep}
+  def markdown_to_ast(_text), do: :erlang.nif_error(:nif_not_loaded)
+
+  @spec markdown_extract_mentions(String.t()) :: [
+          %{id: integer(), suffix: String.t(), position: {integer(), integer()}}
+        ]
+  def markdown_extract_mentions(_text), do: :erlang.nif_error(:nif_not_loaded)
+
+  @spec markdown_extract_links(String.t()) :: [
+          %{
+            kind: :link | :image | :autolink,
+            url: String.t(),
+            rewritten: String.t(),
+            position: {integer(), integer()}
+          }
+        ]
+  def markdown_extract_links(_text), do: :erlang.nif_error(:nif_not_loaded)
+
+  @spec markdown_extract_tags(String.t()) :: [String.t()]
+  def markdown_extract_tags(_text), do: :erlang.nif_error(:nif_not_loaded)
+
+  @spec markdown_lint(String.t(), [integer()] | nil) :: [
+          %{
+            kind: :unclosed_spoiler | :empty_link | :broken_mention | :broken_table,
+            start: {integer(), integer()},
+            end: {integer(), integer()},
+            message: String.t()
+          }
+        ]
+  def markdown_lint(_text, _existing_images), do: :erlang.nif_error(:nif_not_loaded)
+
+  @spec markdown_quote(String.t(), String.t(), String.t(), non_neg_integer()) :: String.t()
+  def markdown_quote(_text, _author, _link, _max_depth), do: :erlang.nif_error(:nif_not_loaded)
+
+  @spec markdown_format(String.t()) :: String.t()
+  def markdown_format(_text), do: :erlang.nif_error(:nif_not_loaded)
+
+  @type diff_options :: %{
+          context_lines: non_neg_integer(),
+          algorithm: :myers | :patience | :lcs,
+          timeout_ms: non_neg_integer()
+        }
+
+  @spec markdown_diff_to_html(
+          String.t(),
+          String.t(),
+          :unified | :split | :rendered,
+          diff_options()
+        ) :: String.t()
+  def markdown_diff_to_html(_old, _new, _mode, _options), do: :erlang.nif_error(:nif_not_loaded)
+
+  @spec markdown_diff_hunks(String.t(), String.t(), diff_options()) :: [
+          %{
+            op: :equal | :delete | :insert | :replace,
+            old_range: {integer(), integer()},
+            new_range: {integer(), integer()},
+            lines: [String.t()],
+            inline_spans: [[{integer(), integer()}]]
+          }
+        ]
+  def markdown_diff_hunks(_old, _new, _options), do: :erlang.nif_error(:nif_not_loaded)
+
+  @spec markdown_diff_stats(String.t(), String.t(), diff_options()) :: %{
+          lines_added: integer(),
+          lines_removed: integer(),
+          chars_added: integer(),
+          chars_removed: integer()
+        }
+  def markdown_diff_stats(_old, _new, _options), do: :erlang.nif_error(:nif_not_loaded)
+
+  @spec markdown_merge(String.t(), String.t(), String.t()) ::
+          {:ok, String.t()}
+          | {:error,
+             [
+               %{
+                 base: {integer(), integer()},
+                 ours: {integer(), integer()},
+                 theirs: {integer(), integer()},
+                 base_text: String.t(),
+                 ours_text: String.t(),
+                 theirs_text: String.t()
+               }
+             ]}
+  def markdown_merge(_base, _ours, _theirs), do: :erlang.nif_error(:nif_not_loaded)
+
+  @spec textile_to_markdown(String.t()) :: %{markdown: String.t(), unconverted: [map()]}
+  def textile_to_markdown(_text), do: :erlang.nif_error(:nif_not_loaded)
 
   @spec camo_image_url(String.t()) :: String.t()
   def camo_image_url(_uri), do: :erlang.nif_error(:nif_not_loaded)
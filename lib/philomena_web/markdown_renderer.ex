@@ -3,9 +3,11 @@ defmodule PhilomenaWeb.MarkdownRenderer do
   alias Philomena.Images.Image
   alias Philomena.Repo
   alias PhilomenaWeb.ImageView
-  import Phoenix.HTML.Link
+  import PhilomenaWeb.AppView, only: [can?: 3]
   import Ecto.Query
 
+  use PhilomenaWeb, :verified_routes
+
   def render_one(item, conn) do
     hd(render_collection([item], conn))
   end
@@ -20,21 +22,22 @@ defmodule PhilomenaWeb.MarkdownRenderer do
       end)
       |> render_representations(conn)
 
-    Enum.map(collection, fn %{body: text} ->
-      (text || "")
-      |> Markdown.to_html(representations)
-      |> Phoenix.HTML.raw()
-    end)
+    collection
+    |> Enum.map(fn %{body: text} -> text || "" end)
+    |> Markdown.to_html_batch(representations)
+    |> Enum.map(&Phoenix.HTML.raw/1)
   end
 
   @doc """
-  Renders a line-by-line diff table between two Markdown sources to safe HTML.
+  Renders a diff between two Markdown sources to safe HTML, in `:unified`
+  (default), `:split` or `:rendered` mode. `options` are passed on to
+  `Philomena.Markdown.to_html_diff/4`.
   """
   # The NIF escapes the source text; only its own diff markup is live
   # sobelow_skip ["XSS.Raw"]
-  def render_diff(old, new) do
+  def render_diff(old, new, mode \\ :unified, options \\ []) do
     (old || "")
-    |> Markdown.to_html_diff(new || "")
+    |> Markdown.to_html_diff(new || "", mode, options)
     |> Phoenix.HTML.raw()
   end
 
@@ -61,12 +64,22 @@ defmodule PhilomenaWeb.MarkdownRenderer do
     |> Phoenix.HTML.raw()
   end
 
+  # This is rendered Markdown for staff-editable content; raw HTML is
+  # cleaned against an allowlist
+  # sobelow_skip ["XSS.Raw"]
+  def render_sanitized(text, conn) do
+    images = find_images(text)
+    representations = render_representations(images, conn)
+
+    text
+    |> Markdown.to_html_sanitized(representations)
+    |> Phoenix.HTML.raw()
+  end
+
   defp find_images(text) do
-    Regex.scan(~r/>>(\d+)([tsp])?/, text, capture: :all_but_first)
-    |> Enum.map(fn matches ->
-      [Enum.at(matches, 0) |> String.to_integer(), Enum.at(matches, 1) || ""]
-    end)
-    |> Enum.filter(fn m -> Enum.at(m, 0) < 2_147_483_647 end)
+    text
+    |> Markdown.extract_mentions()
+    |> Enum.map(&[&1.id, &1.suffix])
   end
 
   defp load_images(images) do
@@ -79,79 +92,65 @@ defmodule PhilomenaWeb.MarkdownRenderer do
     |> Map.new(&{&1.id, &1})
   end
 
-  defp link_suffix(image) do
+  defp image_status(image) do
     cond do
-      not is_nil(image.duplicate_id) ->
-        " (merged)"
-
-      image.hidden_from_users ->
-        " (deleted)"
+      not is_nil(image.duplicate_id) -> :merged
+      image.hidden_from_users -> :deleted
+      not image.approved -> :pending
+      true -> :visible
+    end
+  end
 
-      not image.approved ->
-        " (pending approval)"
+  defp image_embed(image, conn) do
+    uris =
+      image
+      |> ImageView.thumb_urls(can?(conn, :show, image))
+      |> Map.new(fn {version, url} -> {to_string(version), url} end)
+
+    thumbnails =
+      Map.new([:thumb_small, :small, :medium], fn version ->
+        size = ImageView.select_version(image, version)
+
+        {to_string(version),
+         %{
+           size: to_string(size),
+           url: ImageView.thumb_url(image, can?(conn, :show, image), size)
+         }}
+      end)
 
-      true ->
-        ""
-    end
+    %{
+      id: image.id,
+      tag_ids: Enum.map(image.tags, & &1.id),
+      tag_aliases: image.tags |> Enum.flat_map(&[&1 | &1.aliases]) |> Enum.map(& &1.name),
+      score: image.score,
+      faves: image.faves_count,
+      upvotes: image.upvotes_count,
+      downvotes: image.downvotes_count,
+      comment_count: image.comments_count,
+      created_at: DateTime.to_iso8601(image.created_at),
+      sources: Enum.map(image.sources, & &1.source),
+      uris: uris,
+      width: image.image_width,
+      height: image.image_height,
+      aspect_ratio: image.image_aspect_ratio,
+      thumbnails: thumbnails,
+      mime_type: image.image_mime_type,
+      title: ImageView.title_text(image),
+      filter_path: ~p"/filters/#{conn.assigns.current_filter}",
+      blocked_path: static_path(conn, "/images/tagblocked.svg"),
+      status: image_status(image)
+    }
   end
 
   defp render_representations(images, conn) do
-    loaded_images = load_images(images)
-
-    Map.new(images, fn group ->
-      img = loaded_images[Enum.at(group, 0)]
-      text = "#{Enum.at(group, 0)}#{Enum.at(group, 1)}"
-
-      rendered =
-        if img != nil do
-          case group do
-            [_id, "p"] when not img.hidden_from_users and img.approved ->
-              Phoenix.View.render(ImageView, "_image_target.html",
-                embed_display: true,
-                image: img,
-                size: ImageView.select_version(img, :medium),
-                conn: conn
-              )
-
-            [_id, "t"] when not img.hidden_from_users and img.approved ->
-              Phoenix.View.render(ImageView, "_image_target.html",
-                embed_display: true,
-                image: img,
-                size: ImageView.select_version(img, :small),
-                conn: conn
-              )
-
-            [_id, "s"] when not img.hidden_from_users and img.approved ->
-              Phoenix.View.render(ImageView, "_image_target.html",
-                embed_display: true,
-                image: img,
-                size: ImageView.select_version(img, :thumb_small),
-                conn: conn
-              )
-
-            [_id, suffix] when not img.approved ->
-              ">>#{img.id}#{suffix}#{link_suffix(img)}"
-
-            [_id, ""] ->
-              link(">>#{img.id}#{link_suffix(img)}", to: "/images/#{img.id}")
-
-            [_id, suffix] when suffix in ["t", "s", "p"] ->
-              link(">>#{img.id}#{suffix}#{link_suffix(img)}", to: "/images/#{img.id}")
-
-            # This condition should never trigger, but let's leave it here just in case.
-            [id, suffix] ->
-              ">>#{id}#{suffix}"
-          end
-        else
-          ">>#{text}"
-        end
-
-      string_contents =
-        rendered
-        |> Phoenix.HTML.Safe.to_iodata()
-        |> IO.iodata_to_binary()
-
-      {text, string_contents}
-    end)
+    embeds =
+      images
+      |> load_images()
+      |> Map.values()
+      |> Enum.map(&image_embed(&1, conn))
+
+    images
+    |> Enum.map(fn [id, suffix] -> {id, suffix} end)
+    |> Markdown.embed_replacements(embeds)
   end
 end
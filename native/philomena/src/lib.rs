@@ -5,11 +5,28 @@ use std::collections::HashMap;
 mod asyncnif;
 mod camo;
 mod domains;
+mod links;
 mod markdown;
+mod markdown_ast;
+mod markdown_batch;
 mod markdown_diff;
+mod markdown_embeds;
+mod markdown_excerpt;
+mod markdown_extract;
+mod markdown_format;
+mod markdown_highlight;
+mod markdown_limits;
+mod markdown_lint;
+mod markdown_quote;
+mod markdown_sanitize;
+mod markdown_tags;
+mod markdown_text;
+mod markdown_toc;
+mod markdown_users;
 mod remote;
 #[cfg(test)]
 mod tests;
+mod textile;
 mod zip;
 
 #[global_allocator]
@@ -26,14 +43,185 @@ fn markdown_to_html(input: &str, reps: HashMap<String, String>) -> String {
     markdown::to_html(input, reps)
 }
 
+#[rustler::nif]
+fn markdown_set_link_policy(policy: links::LinkPolicy) -> Atom {
+    links::set(policy);
+    rustler::types::atom::ok()
+}
+
+#[rustler::nif(schedule = "DirtyCpu")]
+fn markdown_to_html_batch(items: Vec<(String, HashMap<String, String>)>) -> Vec<String> {
+    markdown_batch::to_html_batch(items)
+}
+
+#[rustler::nif(schedule = "DirtyCpu")]
+fn markdown_to_html_batch_shared(
+    inputs: Vec<String>,
+    reps: HashMap<String, String>,
+) -> Vec<String> {
+    markdown_batch::to_html_batch_shared(inputs, reps)
+}
+
+#[rustler::nif(schedule = "DirtyCpu")]
+fn markdown_to_html_with_options(
+    input: &str,
+    reps: HashMap<String, String>,
+    options: markdown::HtmlOptions,
+) -> String {
+    markdown::to_html_with(input, reps, &options)
+}
+
+#[rustler::nif(schedule = "DirtyCpu")]
+fn markdown_to_html_limited(
+    input: &str,
+    reps: HashMap<String, String>,
+    limits: markdown_limits::Limits,
+) -> Result<String, markdown_limits::LimitError> {
+    markdown_limits::to_html_limited(input, reps, &limits)
+}
+
+#[rustler::nif(schedule = "DirtyCpu")]
+fn markdown_to_html_with_users(
+    input: &str,
+    reps: HashMap<String, String>,
+    users: HashMap<String, String>,
+) -> (String, Vec<String>) {
+    markdown_users::to_html_with_users(input, reps, users)
+}
+
+#[rustler::nif(schedule = "DirtyCpu")]
+fn markdown_to_html_with_tags(
+    input: &str,
+    reps: HashMap<String, String>,
+    states: Option<HashMap<String, markdown_tags::TagState>>,
+) -> (String, Vec<String>) {
+    markdown_tags::to_html_with_tags(input, reps, states)
+}
+
+#[rustler::nif(schedule = "DirtyCpu")]
+fn markdown_to_html_with_embeds(input: &str, embeds: Vec<markdown_embeds::ImageEmbed>) -> String {
+    markdown_embeds::to_html_with_embeds(input, &embeds)
+}
+
+#[rustler::nif]
+fn markdown_embed_replacements(
+    mentions: Vec<(u64, String)>,
+    embeds: Vec<markdown_embeds::ImageEmbed>,
+) -> HashMap<String, String> {
+    markdown_embeds::replacements(mentions, &embeds)
+}
+
 #[rustler::nif(schedule = "DirtyCpu")]
 fn markdown_to_html_unsafe(input: &str, reps: HashMap<String, String>) -> String {
     markdown::to_html_unsafe(input, reps)
 }
 
 #[rustler::nif(schedule = "DirtyCpu")]
-fn markdown_diff_to_html(old: &str, new: &str) -> String {
-    markdown_diff::to_html(old, new)
+fn markdown_to_html_sanitized(
+    input: &str,
+    reps: HashMap<String, String>,
+    policy: markdown_sanitize::SanitizePolicy,
+) -> String {
+    markdown_sanitize::to_html_sanitized(input, reps, &policy)
+}
+
+#[rustler::nif(schedule = "DirtyCpu")]
+fn markdown_to_html_unsafe_with_toc(
+    input: &str,
+    reps: HashMap<String, String>,
+) -> (String, Vec<markdown_toc::TocEntry>) {
+    markdown_toc::to_html_unsafe_with_toc(input, reps)
+}
+
+#[rustler::nif(schedule = "DirtyCpu")]
+fn markdown_to_html_excerpt(
+    input: &str,
+    reps: HashMap<String, String>,
+    excerpt: markdown_excerpt::ExcerptOptions,
+) -> (String, bool) {
+    markdown_excerpt::to_html_excerpt(input, reps, excerpt)
+}
+
+#[rustler::nif(schedule = "DirtyCpu")]
+fn markdown_to_text(input: &str) -> String {
+    markdown_text::to_text(input)
+}
+
+#[rustler::nif(schedule = "DirtyCpu")]
+fn markdown_to_ast(input: &str) -> Result<markdown_ast::Node, markdown_limits::LimitError> {
+    markdown_ast::parse(input)
+}
+
+#[rustler::nif(schedule = "DirtyCpu")]
+fn markdown_extract_mentions(input: &str) -> Vec<markdown_extract::Mention> {
+    markdown_extract::mentions(input)
+}
+
+#[rustler::nif(schedule = "DirtyCpu")]
+fn markdown_extract_links(input: &str) -> Vec<markdown_extract::Link> {
+    markdown_extract::links(input)
+}
+
+#[rustler::nif(schedule = "DirtyCpu")]
+fn markdown_extract_tags(input: &str) -> Vec<String> {
+    markdown_tags::tags(input)
+}
+
+#[rustler::nif(schedule = "DirtyCpu")]
+fn markdown_lint(input: &str, existing_images: Option<Vec<u64>>) -> Vec<markdown_lint::Warning> {
+    markdown_lint::lint(input, existing_images)
+}
+
+#[rustler::nif(schedule = "DirtyCpu")]
+fn markdown_quote(input: &str, author: &str, link: &str, max_depth: usize) -> String {
+    markdown_quote::quote(input, author, link, max_depth)
+}
+
+#[rustler::nif(schedule = "DirtyCpu")]
+fn markdown_format(input: &str) -> String {
+    markdown_format::format(input)
+}
+
+#[rustler::nif(schedule = "DirtyCpu")]
+fn markdown_diff_to_html(
+    old: &str,
+    new: &str,
+    mode: markdown_diff::DiffMode,
+    options: markdown_diff::DiffOptions,
+) -> String {
+    markdown_diff::to_html_with_options(old, new, mode, &options)
+}
+
+#[rustler::nif(schedule = "DirtyCpu")]
+fn markdown_diff_hunks(
+    old: &str,
+    new: &str,
+    options: markdown_diff::DiffOptions,
+) -> Vec<markdown_diff::DiffHunk> {
+    markdown_diff::hunks(old, new, &options)
+}
+
+#[rustler::nif(schedule = "DirtyCpu")]
+fn markdown_diff_stats(
+    old: &str,
+    new: &str,
+    options: markdown_diff::DiffOptions,
+) -> markdown_diff::DiffStats {
+    markdown_diff::stats(old, new, &options)
+}
+
+#[rustler::nif(schedule = "DirtyCpu")]
+fn markdown_merge(
+    base: &str,
+    ours: &str,
+    theirs: &str,
+) -> Result<String, Vec<markdown_diff::Conflict>> {
+    markdown_diff::merge(base, ours, theirs)
+}
+
+#[rustler::nif(schedule = "DirtyCpu")]
+fn textile_to_markdown(input: &str) -> textile::Conversion {
+    textile::to_markdown(input)
 }
 
 // Camo NIF wrappers.
@@ -0,0 +1,168 @@
+use std::sync::{LazyLock, RwLock};
+
+use crate::domains::DomainSet;
+use regex::Regex;
+use rustler::NifMap;
+use url::Url;
+
+/// How rendered links to other sites are treated.
+#[derive(NifMap, Clone, Debug, Default, PartialEq, Eq)]
+pub struct LinkPolicy {
+    /// Open off-site links in a new tab.
+    pub new_tab: bool,
+    /// Hostnames whose links are removed. An entry of the form
+    /// `*.example.com` matches every subdomain of `example.com`.
+    pub blocklist: Vec<String>,
+}
+
+static POLICY: RwLock<LinkPolicy> = RwLock::new(LinkPolicy {
+    new_tab: false,
+    blocklist: Vec::new(),
+});
+
+static ANCHOR: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"<a href="([^"]*)"([^>]*)>"#).unwrap());
+
+/// One `name="value"` attribute. Values are escaped by the renderer, so
+/// they never contain a double quote.
+static ATTRIBUTE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"\s([A-Za-z_:][-A-Za-z0-9_:.]*)="[^"]*""#).unwrap());
+
+/// The policy applied to rendered Markdown, as last set by [`set`].
+pub fn get() -> LinkPolicy {
+    POLICY.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Replace the policy applied to rendered Markdown. The application sets it
+/// from its configuration at startup; until then no policy is applied.
+pub fn set(policy: LinkPolicy) {
+    let policy = LinkPolicy {
+        blocklist: policy
+            .blocklist
+            .iter()
+            .map(|s| s.trim().to_lowercase())
+            .filter(|s| !s.is_empty())
+            .collect(),
+        ..policy
+    };
+
+    *POLICY.write().unwrap_or_else(|e| e.into_inner()) = policy;
+}
+
+/// Rel attribute added to every off-site link.
+pub(crate) const EXTERNAL_REL: &str = "nofollow ugc noopener";
+
+impl LinkPolicy {
+    pub fn is_blocked(&self, host: &str) -> bool {
+        let host = host.to_lowercase();
+
+        self.blocklist
+            .iter()
+            .any(|entry| match entry.strip_prefix("*.") {
+                Some(domain) => host
+                    .strip_suffix(domain)
+                    .is_some_and(|sub| sub.ends_with('.')),
+                None => host == *entry,
+            })
+    }
+}
+
+/// Host of an off-site link, or `None` for links which stay on the site
+/// (relative links, and absolute links to any of `domains`). Protocol-relative
+/// links (`//example.com/`) are resolved as browsers would on the site, which
+/// is served over HTTPS; browsers also read a backslash there as a slash.
+fn external_host(href: &str, domains: Option<&DomainSet>) -> Option<String> {
+    let href = href.replace("&amp;", "&");
+    let protocol_relative = href
+        .get(..2)
+        .is_some_and(|start| start.chars().all(|c| c == '/' || c == '\\'));
+    let url = if protocol_relative {
+        Url::parse(&format!("https:{href}")).ok()?
+    } else {
+        Url::parse(&href).ok()?
+    };
+    let host = url.host_str()?;
+
+    match domains {
+        Some(domains) if domains.contains(host) => None,
+        _ => Some(host.into()),
+    }
+}
+
+/// Whether `href` is an off-site link to a host `policy` blocks.
+pub(crate) fn is_blocked_link(
+    href: &str,
+    domains: Option<&DomainSet>,
+    policy: &LinkPolicy,
+) -> bool {
+    external_host(href, domains).is_some_and(|host| policy.is_blocked(&host))
+}
+
+/// Apply `policy` to the links in rendered HTML: off-site links get
+/// `rel="nofollow ugc noopener"` (and `target="_blank"` when configured),
+/// and links to blocklisted hosts are replaced by a marked span holding
+/// only the link text.
+pub fn apply(html: &str, domains: Option<&DomainSet>, policy: &LinkPolicy) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+
+    while let Some(caps) = ANCHOR.captures(rest) {
+        let tag = caps.get(0).unwrap();
+        let href = &caps[1];
+        let attrs = &caps[2];
+
+        out.push_str(&rest[..tag.start()]);
+        rest = &rest[tag.end()..];
+
+        let Some(host) = external_host(href, domains) else {
+            out.push_str(tag.as_str());
+            continue;
+        };
+
+        if policy.is_blocked(&host) {
+            out.push_str("<span class=\"link--blocked\" title=\"Link removed\">");
+
+            match rest.find("</a>") {
+                Some(end) => {
+                    out.push_str(&rest[..end]);
+                    rest = &rest[end + "</a>".len()..];
+                }
+                None => {
+                    out.push_str(rest);
+                    rest = "";
+                }
+            }
+
+            out.push_str("</span>");
+            continue;
+        }
+
+        out.push_str("<a href=\"");
+        out.push_str(href);
+        out.push('"');
+        out.push_str(attrs);
+
+        if !has_attribute(attrs, "rel") {
+            out.push_str(" rel=\"");
+            out.push_str(EXTERNAL_REL);
+            out.push('"');
+        }
+
+        if policy.new_tab && !has_attribute(attrs, "target") {
+            out.push_str(" target=\"_blank\"");
+        }
+
+        out.push('>');
+    }
+
+    out.push_str(rest);
+    out
+}
+
+/// Whether the attributes of a tag include `name`, looking only at attribute
+/// names so that a value such as a title mentioning ` rel=` does not count.
+fn has_attribute(attrs: &str, name: &str) -> bool {
+    ATTRIBUTE
+        .captures_iter(attrs)
+        .any(|caps| caps[1].eq_ignore_ascii_case(name))
+}
@@ -1,7 +1,76 @@
-use crate::{camo, domains};
-use comrak::Options;
+use crate::markdown_highlight::Highlighter;
+use crate::{camo, domains, links};
+use comrak::nodes::{AstNode, NodeValue};
+use comrak::options::Plugins;
+use comrak::{Arena, Options, parse_document};
+use regex::Regex;
+use rustler::{NifMap, NifUnitEnum};
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::{Arc, LazyLock};
+
+/// Named feature sets, so each place that renders user content can restrict
+/// what it accepts without post-filtering the HTML.
+#[derive(NifUnitEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Profile {
+    Comment,
+    Signature,
+    UserDescription,
+    TagDescription,
+    StaticPage,
+    Title,
+}
+
+/// Constructs a profile can switch off.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Features {
+    pub tables: bool,
+    pub images: bool,
+    pub headings: bool,
+    pub description_lists: bool,
+}
+
+impl Profile {
+    pub fn features(self) -> Features {
+        let all = Features {
+            tables: true,
+            images: true,
+            headings: true,
+            description_lists: true,
+        };
+
+        match self {
+            Profile::Comment | Profile::TagDescription | Profile::StaticPage => all,
+            Profile::UserDescription => Features {
+                headings: false,
+                ..all
+            },
+            Profile::Signature | Profile::Title => Features {
+                tables: false,
+                images: false,
+                headings: false,
+                description_lists: false,
+            },
+        }
+    }
+}
+
+/// Per-call rendering options for [`to_html_with`].
+#[derive(NifMap, Clone, Debug)]
+pub struct HtmlOptions {
+    pub profile: Profile,
+    /// Languages whose fenced code blocks are syntax highlighted.
+    pub highlight: Vec<String>,
+    /// Emit `data-sourcepos` attributes on block elements.
+    pub sourcepos: bool,
+}
+
+/// A `data-sourcepos` attribute on an inline element.
+static INLINE_SOURCEPOS: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r#"<(a|code|del|em|img|ins|mark|s|span|strong|sub|sup|u)( [^>]*?)? data-sourcepos="[^"]*""#,
+    )
+    .unwrap()
+});
 
 pub fn common_options() -> Options<'static> {
     let mut options = Options::default();
@@ -40,14 +109,121 @@ pub fn to_html(input: &str, reps: HashMap<String, String>) -> String {
     let mut options = common_options();
     options.extension.replacements = Some(reps);
 
-    comrak::markdown_to_html(input, &options)
+    finish(comrak::markdown_to_html(input, &options))
 }
 
-pub fn to_html_unsafe(input: &str, reps: HashMap<String, String>) -> String {
+/// Options for trusted input: raw HTML is passed through.
+pub fn unsafe_options() -> Options<'static> {
     let mut options = common_options();
     options.render.escape = false;
     options.render.r#unsafe = true;
+    options
+}
+
+pub fn to_html_unsafe(input: &str, reps: HashMap<String, String>) -> String {
+    let mut options = unsafe_options();
     options.extension.replacements = Some(reps);
 
-    comrak::markdown_to_html(input, &options)
+    finish(comrak::markdown_to_html(input, &options))
+}
+
+/// Apply the site's external link policy to rendered HTML.
+pub(crate) fn finish(html: String) -> String {
+    links::apply(&html, domains::get().as_ref(), &links::get())
+}
+
+/// The common options, narrowed to what `profile` allows. Constructs which
+/// comrak cannot switch off at parse time are removed by [`restrict`].
+/// Profiles only take features away: raw HTML is escaped under every one of
+/// them, and trusted input goes through [`to_html_unsafe`] instead.
+pub fn profile_options(profile: Profile) -> Options<'static> {
+    let features = profile.features();
+    let mut options = common_options();
+
+    options.extension.table = features.tables;
+    options.extension.description_lists = features.description_lists;
+
+    options
+}
+
+/// Rewrite a parsed document so it only uses what `profile` allows: headings
+/// become paragraphs and images are replaced by their alt text.
+pub fn restrict<'a>(root: &'a AstNode<'a>, profile: Profile) {
+    let features = profile.features();
+    let nodes: Vec<_> = root.descendants().collect();
+
+    for node in nodes {
+        let (heading, image) = {
+            let ast = node.data.borrow();
+            (
+                matches!(ast.value, NodeValue::Heading(..)),
+                matches!(ast.value, NodeValue::Image(..)),
+            )
+        };
+
+        if heading && !features.headings {
+            node.data.borrow_mut().value = NodeValue::Paragraph;
+        } else if image && !features.images {
+            let children: Vec<_> = node.children().collect();
+            for child in children {
+                node.insert_before(child);
+            }
+            node.detach();
+        }
+    }
+}
+
+/// Format a parsed (and possibly rewritten) document as HTML.
+pub fn format_html<'a>(root: &'a AstNode<'a>, options: &Options) -> String {
+    format_html_with_plugins(root, options, &Plugins::default())
+}
+
+pub fn format_html_with_plugins<'a>(
+    root: &'a AstNode<'a>,
+    options: &Options,
+    plugins: &Plugins,
+) -> String {
+    let mut html = String::new();
+    comrak::format_html_with_plugins(root, options, &mut html, plugins)
+        .expect("formatting to a String cannot fail");
+
+    html
+}
+
+/// Renders `input` with the profile and options in `opts`. Profiles without
+/// images ignore `reps`, so image mentions stay as written.
+pub fn to_html_with(input: &str, reps: HashMap<String, String>, opts: &HtmlOptions) -> String {
+    let mut options = profile_options(opts.profile);
+    options.render.sourcepos = opts.sourcepos;
+    options.extension.replacements = Some(if opts.profile.features().images {
+        reps
+    } else {
+        HashMap::new()
+    });
+
+    let arena = Arena::new();
+    let root = parse_document(&arena, input, &options);
+    restrict(root, opts.profile);
+
+    let highlighter = Highlighter::new(opts.highlight.clone());
+    let mut plugins = Plugins::default();
+
+    if !opts.highlight.is_empty() {
+        plugins.render.codefence_syntax_highlighter = Some(&highlighter);
+    }
+
+    let html = format_html_with_plugins(root, &options, &plugins);
+
+    if opts.sourcepos {
+        finish(strip_inline_sourcepos(&html))
+    } else {
+        finish(html)
+    }
+}
+
+/// Remove `data-sourcepos` from inline elements, keeping it on blocks only.
+/// Quote-and-reply maps selections to whole blocks, and inline positions
+/// would only bloat the output.
+pub fn strip_inline_sourcepos(html: &str) -> String {
+    INLINE_SOURCEPOS.replace_all(html, "<$1$2").into_owned()
 }
@@ -0,0 +1,206 @@
+// Structured view of a parsed Markdown document.
+//
+// The document is parsed with the same options the renderer uses and the
+// resulting comrak AST is converted into plain Rust values, which encode to
+// nested Elixir maps of the form
+//
+//     %{kind: :paragraph, attrs: [...], sourcepos: {{1, 1}, {1, 5}}, children: [...]}
+//
+// `attrs` is a keyword list holding the node-specific data (link URLs,
+// heading levels, code literals and so on). Node kinds the converter does
+// not know about are reported as `:unknown` with their children intact, so
+// the tree is always complete.
+//
+// Both the conversion and the encoding recurse once per level of the tree,
+// so documents nested deeper than `MAX_DEPTH` are refused rather than
+// risking the scheduler's stack.
+
+use crate::markdown;
+use crate::markdown_limits::{self, LimitError};
+use comrak::nodes::{AstNode, ListType, NodeValue};
+use comrak::{Arena, parse_document};
+use rustler::{Atom, Encoder, Env, NifMap, Term};
+
+/// Deepest nesting of nodes, below the document, which is converted.
+const MAX_DEPTH: usize = 128;
+
+/// A single attribute value on a [`Node`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AttrValue {
+    Str(String),
+    Int(i64),
+    Bool(bool),
+}
+
+impl Encoder for AttrValue {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        match self {
+            AttrValue::Str(s) => s.encode(env),
+            AttrValue::Int(i) => i.encode(env),
+            AttrValue::Bool(b) => b.encode(env),
+        }
+    }
+}
+
+/// Start and end of a node in the source, as 1-based `(line, column)` pairs.
+pub type Sourcepos = ((usize, usize), (usize, usize));
+
+/// One node of the document tree.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Node {
+    pub kind: &'static str,
+    pub attrs: Vec<(&'static str, AttrValue)>,
+    pub sourcepos: Sourcepos,
+    pub children: Vec<Node>,
+}
+
+#[derive(NifMap)]
+struct NodeTerm<'a> {
+    kind: Atom,
+    attrs: Vec<(Atom, Term<'a>)>,
+    sourcepos: Sourcepos,
+    children: Vec<Term<'a>>,
+}
+
+fn atom(env: Env, name: &str) -> Atom {
+    Atom::from_str(env, name).expect("atom name too long")
+}
+
+impl Encoder for Node {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        NodeTerm {
+            kind: atom(env, self.kind),
+            attrs: self
+                .attrs
+                .iter()
+                .map(|(key, value)| (atom(env, key), value.encode(env)))
+                .collect(),
+            sourcepos: self.sourcepos,
+            children: self.children.iter().map(|c| c.encode(env)).collect(),
+        }
+        .encode(env)
+    }
+}
+
+/// Parse `input` with the common renderer options and return its AST, or
+/// an error if it is nested too deeply to convert.
+pub fn parse(input: &str) -> Result<Node, LimitError> {
+    let mut options = markdown::common_options();
+    options.extension.replacements = Some(Default::default());
+
+    let arena = Arena::new();
+    let root = parse_document(&arena, input, &options);
+    let (depth, _) = markdown_limits::measure(root);
+
+    if depth > MAX_DEPTH {
+        return Err(LimitError::NestingTooDeep);
+    }
+
+    Ok(convert(root))
+}
+
+fn convert<'a>(node: &'a AstNode<'a>) -> Node {
+    let ast = node.data.borrow();
+    let (kind, attrs) = describe(&ast.value);
+    let sourcepos = (
+        (ast.sourcepos.start.line, ast.sourcepos.start.column),
+        (ast.sourcepos.end.line, ast.sourcepos.end.column),
+    );
+    drop(ast);
+
+    Node {
+        kind,
+        attrs,
+        sourcepos,
+        children: node.children().map(convert).collect(),
+    }
+}
+
+fn str_attr(value: impl ToString) -> AttrValue {
+    AttrValue::Str(value.to_string())
+}
+
+fn int_attr(value: impl TryInto<i64>) -> AttrValue {
+    AttrValue::Int(value.try_into().unwrap_or(i64::MAX))
+}
+
+/// Map a comrak node value to its kind name and attributes.
+fn describe(value: &NodeValue) -> (&'static str, Vec<(&'static str, AttrValue)>) {
+    match value {
+        NodeValue::Document => ("document", vec![]),
+        NodeValue::BlockQuote => ("block_quote", vec![]),
+        NodeValue::List(list) | NodeValue::Item(list) => {
+            let kind = match value {
+                NodeValue::List(_) => "list",
+                _ => "item",
+            };
+            let list_type = match list.list_type {
+                ListType::Bullet => "bullet",
+                ListType::Ordered => "ordered",
+            };
+
+            (
+                kind,
+                vec![
+                    ("list_type", str_attr(list_type)),
+                    ("start", int_attr(list.start)),
+                    ("tight", AttrValue::Bool(list.tight)),
+                ],
+            )
+        }
+        NodeValue::DescriptionList => ("description_list", vec![]),
+        NodeValue::DescriptionItem(_) => ("description_item", vec![]),
+        NodeValue::DescriptionTerm => ("description_term", vec![]),
+        NodeValue::DescriptionDetails => ("description_details", vec![]),
+        NodeValue::CodeBlock(block) => (
+            "code_block",
+            vec![
+                ("fenced", AttrValue::Bool(block.fenced)),
+                ("info", str_attr(&block.info)),
+                ("literal", str_attr(&block.literal)),
+            ],
+        ),
+        NodeValue::HtmlBlock(block) => ("html_block", vec![("literal", str_attr(&block.literal))]),
+        NodeValue::Paragraph => ("paragraph", vec![]),
+        NodeValue::Heading(heading) => ("heading", vec![("level", int_attr(heading.level))]),
+        NodeValue::ThematicBreak => ("thematic_break", vec![]),
+        NodeValue::Table(table) => (
+            "table",
+            vec![
+                ("num_columns", int_attr(table.num_columns)),
+                ("num_rows", int_attr(table.num_rows)),
+            ],
+        ),
+        NodeValue::TableRow(header) => ("table_row", vec![("header", AttrValue::Bool(*header))]),
+        NodeValue::TableCell => ("table_cell", vec![]),
+        NodeValue::Text(text) => ("text", vec![("literal", str_attr(text))]),
+        NodeValue::SoftBreak => ("softbreak", vec![]),
+        NodeValue::LineBreak => ("linebreak", vec![]),
+        NodeValue::Code(code) => ("code", vec![("literal", str_attr(&code.literal))]),
+        NodeValue::HtmlInline(html) => ("html_inline", vec![("literal", str_attr(html))]),
+        NodeValue::Raw(raw) => ("raw", vec![("literal", str_attr(raw))]),
+        NodeValue::Emph => ("emph", vec![]),
+        NodeValue::Strong => ("strong", vec![]),
+        NodeValue::Strikethrough => ("strikethrough", vec![]),
+        NodeValue::Superscript => ("superscript", vec![]),
+        NodeValue::Subscript => ("subscript", vec![]),
+        NodeValue::Underline => ("underline", vec![]),
+        NodeValue::SpoileredText => ("spoiler", vec![]),
+        NodeValue::Escaped => ("escaped", vec![]),
+        NodeValue::Link(link) | NodeValue::Image(link) => {
+            let kind = match value {
+                NodeValue::Link(_) => "link",
+                _ => "image",
+            };
+
+            (
+                kind,
+                vec![
+                    ("url", str_attr(&link.url)),
+                    ("title", str_attr(&link.title)),
+                ],
+            )
+        }
+        _ => ("unknown", vec![]),
+    }
+}
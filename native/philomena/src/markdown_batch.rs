@@ -0,0 +1,181 @@
+// Batch rendering of many Markdown documents in one call.
+//
+// A page of comments would otherwise cost one NIF call per comment, each
+// decoding its own replacement map and rebuilding the common options with
+// their rewriter closures. Here the options are built once, and documents
+// are handed to a small pool of worker threads shared by every batch, so
+// concurrent batches queue for the same few threads rather than each
+// spawning their own. Results are written back by index, so they come out
+// in the order the documents went in. A document whose rendering panics is
+// shown as its escaped source, so one bad comment does not fail the page.
+
+use crate::markdown;
+use comrak::Options;
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::thread;
+
+/// Upper bound on worker threads shared by all batches.
+const MAX_WORKERS: usize = 8;
+
+static POOL: LazyLock<Pool> = LazyLock::new(|| {
+    Pool::new(
+        thread::available_parallelism()
+            .map_or(1, |n| n.get())
+            .min(MAX_WORKERS),
+    )
+});
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A fixed set of threads running jobs from a shared queue.
+struct Pool {
+    jobs: Sender<Job>,
+}
+
+impl Pool {
+    fn new(size: usize) -> Self {
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let queue = Arc::new(Mutex::new(queue));
+
+        for n in 0..size {
+            let queue = queue.clone();
+
+            thread::Builder::new()
+                .name(format!("markdown-batch-{n}"))
+                .spawn(move || work(&queue))
+                .expect("failed to spawn batch worker");
+        }
+
+        Self { jobs }
+    }
+
+    fn submit(&self, job: Job) {
+        self.jobs.send(job).expect("batch workers outlive the pool");
+    }
+}
+
+/// Run jobs until the queue is closed. A job which panics only loses its
+/// own result, not the worker.
+fn work(queue: &Mutex<Receiver<Job>>) {
+    loop {
+        let job = queue.lock().unwrap_or_else(|e| e.into_inner()).recv();
+
+        match job {
+            Ok(job) => {
+                let _ = panic::catch_unwind(AssertUnwindSafe(job));
+            }
+            Err(_) => return,
+        }
+    }
+}
+
+/// A batch item, which can be shown as its Markdown source if rendering it
+/// fails.
+pub(crate) trait Document {
+    fn source(&self) -> &str;
+}
+
+impl Document for String {
+    fn source(&self) -> &str {
+        self
+    }
+}
+
+impl Document for (String, HashMap<String, String>) {
+    fn source(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Render each `(source, reps)` pair, returning the HTML in input order.
+pub fn to_html_batch(items: Vec<(String, HashMap<String, String>)>) -> Vec<String> {
+    let base = markdown::common_options();
+
+    render_all(items, move |(input, reps)| {
+        let mut options = base.clone();
+        options.extension.replacements = Some(reps.clone());
+
+        render(input, &options)
+    })
+}
+
+/// Render each source with one shared replacement map, returning the HTML in
+/// input order.
+pub fn to_html_batch_shared(inputs: Vec<String>, reps: HashMap<String, String>) -> Vec<String> {
+    let mut options = markdown::common_options();
+    options.extension.replacements = Some(reps);
+
+    render_all(inputs, move |input| render(input, &options))
+}
+
+fn render(input: &str, options: &Options) -> String {
+    markdown::finish(comrak::markdown_to_html(input, options))
+}
+
+/// Apply `f` to `item`, falling back to its escaped source if `f` panics.
+fn render_or_escape<T, F>(f: &F, item: &T) -> String
+where
+    T: Document,
+    F: Fn(&T) -> String,
+{
+    panic::catch_unwind(AssertUnwindSafe(|| f(item))).unwrap_or_else(|_| escaped(item.source()))
+}
+
+/// `source` as a paragraph of plain text.
+fn escaped(source: &str) -> String {
+    let mut html = String::from("<div class=\"paragraph\">");
+
+    for ch in source.chars() {
+        match ch {
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            _ => html.push(ch),
+        }
+    }
+
+    html.push_str("</div>\n");
+    html
+}
+
+/// Apply `f` to every item on the shared pool, keeping the order of the
+/// results. The calling thread waits for the batch to finish.
+pub(crate) fn render_all<T, F>(items: Vec<T>, f: F) -> Vec<String>
+where
+    T: Document + Send + 'static,
+    F: Fn(&T) -> String + Send + Sync + 'static,
+{
+    if items.len() <= 1 {
+        return items
+            .iter()
+            .map(|item| render_or_escape(&f, item))
+            .collect();
+    }
+
+    let len = items.len();
+    let f = Arc::new(f);
+    let (sender, receiver) = mpsc::channel();
+
+    for (index, item) in items.into_iter().enumerate() {
+        let f = f.clone();
+        let sender = sender.clone();
+
+        POOL.submit(Box::new(move || {
+            let _ = sender.send((index, render_or_escape(&*f, &item)));
+        }));
+    }
+
+    drop(sender);
+
+    let mut results = vec![String::new(); len];
+
+    for (index, html) in receiver {
+        results[index] = html;
+    }
+
+    results
+}
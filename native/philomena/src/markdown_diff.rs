@@ -4,74 +4,712 @@
 // table: each line becomes a row with old/new line-number gutters, changed
 // rows are marked with `diff__row--del` / `diff__row--ins`, and runs of
 // unchanged lines beyond the context window collapse into an
-// "N unchanged lines" separator row. When a line is edited in place, the
-// exact changed words inside it are additionally wrapped in
-// `<del class="diff__hl">` / `<ins class="diff__hl">`.
+// "N unchanged lines" separator row. The collapsed lines are not dropped:
+// each separator row gets its own `<tbody class="diff__gap">`, followed by a
+// `<tbody class="diff__hidden hidden">` holding the hidden rows, so the page
+// can expand them in place. The context size, diff algorithm and timeout
+// can be set per call. When a line is edited in place, the exact changed
+// words inside it are additionally wrapped in `<del class="diff__hl">` /
+// `<ins class="diff__hl">`.
+//
+// In split mode the table has old and new sides instead, each with its own
+// gutter: unchanged lines fill both, and the deleted and inserted lines of
+// an edit are paired up row by row, so an edited line sits next to its
+// replacement with the word-level highlights on each side.
 //
 // The source text is HTML-escaped as it is emitted, so untrusted input stays
 // inert; the only live markup is the table structure and the diff wrappers.
+//
+// Rendered mode compares what readers see rather than the source. Both
+// revisions go through the user-content renderer, each top-level block is
+// rendered on its own, and the two lists of blocks are diffed; removed and
+// added blocks are wrapped in `<del class="diff__block">` and
+// `<ins class="diff__block">`. Only renderer output and the wrappers appear,
+// so the result is as safe as rendered user content.
+//
+// Three-way merging takes the source of a common base revision and two
+// concurrent edits of it, diffs each edit against the base, and applies
+// both sets of changes. Changes touching the same or adjacent base lines
+// conflict unless both edits made the same change.
+//
+// For callers which need data rather than markup, the same hunks are also
+// available as structured terms, and a stats-only variant counts added and
+// removed lines and characters without building any output. Counting
+// characters diffs each edited run of lines again, so all of those diffs
+// share one time budget.
 
-use similar::{Algorithm, ChangeTag, InlineChange, TextDiff};
+use crate::markdown;
+use comrak::{Arena, parse_document};
+use rustler::{NifMap, NifUnitEnum};
+use similar::{Algorithm, ChangeTag, DiffTag, InlineChange, TextDiff};
 use std::fmt::Write;
-use std::time::Duration;
+use std::ops::Range;
+use std::time::{Duration, Instant};
 
-/// Number of unchanged lines shown around each hunk.
+/// Number of unchanged lines shown around each hunk by default.
 const CONTEXT_LINES: usize = 3;
 
-/// Render an HTML diff table comparing `old` to `new`.
-pub fn to_html(old: &str, new: &str) -> String {
+/// Time allowed for a diff by default, after which it falls back to a
+/// coarser result.
+const TIMEOUT_MS: u64 = 50;
+
+/// What a diff compares and how it is laid out.
+#[derive(NifUnitEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiffMode {
+    /// Source lines in one text column, deletions stacked above insertions.
+    Unified,
+    /// Source lines with old text on the left and new text on the right,
+    /// changed lines paired in the same row.
+    Split,
+    /// Rendered blocks, with removed and added blocks marked.
+    Rendered,
+}
+
+/// Diff algorithm, as implemented by `similar`.
+#[derive(NifUnitEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiffAlgorithm {
+    Myers,
+    Patience,
+    Lcs,
+}
+
+impl From<DiffAlgorithm> for Algorithm {
+    fn from(algorithm: DiffAlgorithm) -> Self {
+        match algorithm {
+            DiffAlgorithm::Myers => Algorithm::Myers,
+            DiffAlgorithm::Patience => Algorithm::Patience,
+            DiffAlgorithm::Lcs => Algorithm::Lcs,
+        }
+    }
+}
+
+/// How a diff is computed.
+#[derive(NifMap, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DiffOptions {
+    /// Unchanged lines shown around each hunk.
+    pub context_lines: usize,
+    pub algorithm: DiffAlgorithm,
+    pub timeout_ms: u64,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        DiffOptions {
+            context_lines: CONTEXT_LINES,
+            algorithm: DiffAlgorithm::Patience,
+            timeout_ms: TIMEOUT_MS,
+        }
+    }
+}
+
+/// One line of either document, with its content already rendered.
+struct Line {
+    tag: ChangeTag,
+    old_index: Option<usize>,
+    new_index: Option<usize>,
+    content: String,
+}
+
+/// A run of lines from one diff operation, or a collapsed run of unchanged
+/// lines.
+enum Block {
+    Lines(Vec<Line>),
+    Gap(Vec<Line>),
+}
+
+/// Render an HTML diff comparing `old` to `new` in the given mode, computed
+/// with `options`.
+pub fn to_html_with_options(old: &str, new: &str, mode: DiffMode, options: &DiffOptions) -> String {
+    match mode {
+        DiffMode::Unified => render_unified(&blocks(old, new, options)),
+        DiffMode::Split => render_split(&blocks(old, new, options)),
+        DiffMode::Rendered => render_rendered(old, new, options),
+    }
+}
+
+/// Diff `old` against `new` line by line, grouping changes into hunks with
+/// the configured lines of context and collapsing the unchanged lines
+/// between them.
+fn blocks(old: &str, new: &str, options: &DiffOptions) -> Vec<Block> {
     let old = normalize(old);
     let new = normalize(new);
 
-    let diff = TextDiff::configure()
-        .algorithm(Algorithm::Patience)
-        .timeout(Duration::from_millis(50))
-        .diff_lines(old.as_str(), new.as_str());
-
-    let mut rows = String::new();
-    let groups = diff.grouped_ops(CONTEXT_LINES);
+    let diff = line_diff(&old, &new, options);
+    let groups = diff.grouped_ops(options.context_lines);
 
     if groups.is_empty() {
         // Identical revisions: show the whole document as plain context.
-        for change in diff.iter_all_changes() {
+        let lines = diff
+            .iter_all_changes()
+            .map(|change| {
+                let mut content = String::new();
+                escape_into(&mut content, trim_newline(change.value()));
+
+                Line {
+                    tag: ChangeTag::Equal,
+                    old_index: change.old_index(),
+                    new_index: change.new_index(),
+                    content,
+                }
+            })
+            .collect();
+
+        return vec![Block::Lines(lines)];
+    }
+
+    let old_lines = diff.old_slices();
+    let old_total = old_lines.len();
+    let new_total = diff.new_slices().len();
+    let mut shown_to = 0;
+    let mut blocks = vec![];
+
+    for group in &groups {
+        // Groups start with unchanged context, so the lines before them
+        // are offset by the same amount in both documents.
+        let (start, new_start) = group
+            .first()
+            .map_or((0, 0), |op| (op.old_range().start, op.new_range().start));
+        push_gap(&mut blocks, old_lines, shown_to..start, start, new_start);
+
+        for op in group {
+            let lines = diff
+                .iter_inline_changes(op)
+                .map(|change| Line {
+                    tag: change.tag(),
+                    old_index: change.old_index(),
+                    new_index: change.new_index(),
+                    content: inline_content(&change),
+                })
+                .collect();
+
+            blocks.push(Block::Lines(lines));
+        }
+
+        shown_to = group.last().map_or(shown_to, |op| op.old_range().end);
+    }
+
+    push_gap(
+        &mut blocks,
+        old_lines,
+        shown_to..old_total,
+        old_total,
+        new_total,
+    );
+    blocks
+}
+
+/// Diff `old` against `new` line by line.
+fn line_diff<'a>(old: &'a str, new: &'a str, options: &DiffOptions) -> TextDiff<'a, 'a, 'a, str> {
+    TextDiff::configure()
+        .algorithm(options.algorithm.into())
+        .timeout(Duration::from_millis(options.timeout_ms))
+        .diff_lines(old, new)
+}
+
+/// Collapse the unchanged old lines in `range`. `old_end` and `new_end` are
+/// the indices of the line following the gap in each document.
+fn push_gap(
+    blocks: &mut Vec<Block>,
+    old_lines: &[&str],
+    range: Range<usize>,
+    old_end: usize,
+    new_end: usize,
+) {
+    if range.is_empty() {
+        return;
+    }
+
+    let lines = range
+        .map(|i| {
             let mut content = String::new();
-            escape_into(&mut content, trim_newline(change.value()));
-            push_row(
-                &mut rows,
-                ChangeTag::Equal,
-                change.old_index(),
-                change.new_index(),
-                &content,
-            );
+            escape_into(&mut content, trim_newline(old_lines[i]));
+
+            Line {
+                tag: ChangeTag::Equal,
+                old_index: Some(i),
+                new_index: Some(i + new_end - old_end),
+                content,
+            }
+        })
+        .collect();
+
+    blocks.push(Block::Gap(lines));
+}
+
+/// A diff table being written. Rows go into plain `<tbody>` sections,
+/// which are closed around each collapsed gap.
+struct Table {
+    out: String,
+    open: bool,
+}
+
+impl Table {
+    fn new(class: &str) -> Self {
+        Table {
+            out: format!("<table class=\"{class}\">"),
+            open: false,
+        }
+    }
+
+    /// The open row section, starting one if needed.
+    fn rows(&mut self) -> &mut String {
+        if !self.open {
+            self.out.push_str("<tbody>");
+            self.open = true;
         }
-    } else {
-        let old_total = diff.ops().last().map_or(0, |op| op.old_range().end);
-        let mut shown_to = 0;
 
-        for group in &groups {
-            let start = group.first().map_or(0, |op| op.old_range().start);
-            push_gap(&mut rows, start - shown_to);
+        &mut self.out
+    }
+
+    /// Write a collapsed gap over `lines`: a section with the separator
+    /// `row`, addressed by the gap's first line numbers and length, then a
+    /// hidden section with the `hidden` rows.
+    fn gap(&mut self, lines: &[Line], row: &str, hidden: &str) {
+        self.close();
+
+        let first = &lines[0];
+        let _ = write!(
+            self.out,
+            "<tbody class=\"diff__gap\" data-old-start=\"{}\" data-new-start=\"{}\" data-count=\"{}\">{row}</tbody>\
+             <tbody class=\"diff__hidden hidden\">{hidden}</tbody>",
+            first.old_index.map_or(0, |i| i + 1),
+            first.new_index.map_or(0, |i| i + 1),
+            lines.len()
+        );
+    }
+
+    fn close(&mut self) {
+        if self.open {
+            self.out.push_str("</tbody>");
+            self.open = false;
+        }
+    }
 
-            for op in group {
-                for change in diff.iter_inline_changes(op) {
-                    let content = inline_content(&change);
+    fn finish(mut self) -> String {
+        self.close();
+        self.out.push_str("</table>");
+        self.out
+    }
+}
+
+fn render_unified(blocks: &[Block]) -> String {
+    let mut table = Table::new("diff");
+
+    for block in blocks {
+        match block {
+            Block::Lines(lines) => {
+                let rows = table.rows();
+
+                for line in lines {
                     push_row(
-                        &mut rows,
-                        change.tag(),
-                        change.old_index(),
-                        change.new_index(),
-                        &content,
+                        rows,
+                        line.tag,
+                        line.old_index,
+                        line.new_index,
+                        &line.content,
                     );
                 }
             }
+            Block::Gap(lines) => {
+                let row = format!(
+                    "<tr class=\"diff__row diff__row--gap\"><td class=\"diff__gutter\"></td><td class=\"diff__gutter\"></td><td class=\"diff__text\">{}</td></tr>",
+                    gap_text(lines.len())
+                );
+                let mut hidden = String::new();
 
-            shown_to = group.last().map_or(shown_to, |op| op.old_range().end);
+                for line in lines {
+                    push_row(
+                        &mut hidden,
+                        line.tag,
+                        line.old_index,
+                        line.new_index,
+                        &line.content,
+                    );
+                }
+
+                table.gap(lines, &row, &hidden);
+            }
         }
+    }
+
+    table.finish()
+}
 
-        push_gap(&mut rows, old_total - shown_to);
+fn render_split(blocks: &[Block]) -> String {
+    let mut table = Table::new("diff diff--split");
+
+    for block in blocks {
+        match block {
+            Block::Lines(lines) => {
+                let rows = table.rows();
+                let (equal, changed): (Vec<&Line>, Vec<&Line>) =
+                    lines.iter().partition(|line| line.tag == ChangeTag::Equal);
+
+                for line in equal {
+                    push_split_row(rows, Some(line), Some(line));
+                }
+
+                let deleted: Vec<&Line> = changed
+                    .iter()
+                    .copied()
+                    .filter(|line| line.tag == ChangeTag::Delete)
+                    .collect();
+                let inserted: Vec<&Line> = changed
+                    .iter()
+                    .copied()
+                    .filter(|line| line.tag == ChangeTag::Insert)
+                    .collect();
+
+                for i in 0..deleted.len().max(inserted.len()) {
+                    push_split_row(rows, deleted.get(i).copied(), inserted.get(i).copied());
+                }
+            }
+            Block::Gap(lines) => {
+                let row = format!(
+                    "<tr class=\"diff__row diff__row--gap\"><td class=\"diff__gutter\"></td><td class=\"diff__text\" colspan=\"3\">{}</td></tr>",
+                    gap_text(lines.len())
+                );
+                let mut hidden = String::new();
+
+                for line in lines {
+                    push_split_row(&mut hidden, Some(line), Some(line));
+                }
+
+                table.gap(lines, &row, &hidden);
+            }
+        }
     }
 
-    format!("<table class=\"diff\"><tbody>{rows}</tbody></table>")
+    table.finish()
+}
+
+fn render_rendered(old: &str, new: &str, options: &DiffOptions) -> String {
+    let old = rendered_blocks(old);
+    let new = rendered_blocks(new);
+    let old: Vec<&str> = old.iter().map(String::as_str).collect();
+    let new: Vec<&str> = new.iter().map(String::as_str).collect();
+
+    let diff = TextDiff::configure()
+        .algorithm(options.algorithm.into())
+        .timeout(Duration::from_millis(options.timeout_ms))
+        .diff_slices(&old, &new);
+
+    let mut out = String::from("<div class=\"diff diff--rendered\">");
+
+    for change in diff.iter_all_changes() {
+        let block = change.value();
+
+        match change.tag() {
+            ChangeTag::Equal => out.push_str(block),
+            ChangeTag::Delete => {
+                let _ = write!(out, "<del class=\"diff__block\">{block}</del>");
+            }
+            ChangeTag::Insert => {
+                let _ = write!(out, "<ins class=\"diff__block\">{block}</ins>");
+            }
+        }
+    }
+
+    out.push_str("</div>");
+    out
+}
+
+/// Render each top-level block of user-input `input` separately. Image
+/// mentions are not resolved.
+fn rendered_blocks(input: &str) -> Vec<String> {
+    let mut options = markdown::common_options();
+    options.extension.replacements = Some(Default::default());
+
+    let arena = Arena::new();
+    let root = parse_document(&arena, &normalize(input), &options);
+
+    root.children()
+        .map(|node| markdown::finish(markdown::format_html(node, &options)))
+        .collect()
+}
+
+/// Text of the separator row for `count` collapsed unchanged lines.
+fn gap_text(count: usize) -> String {
+    let noun = if count == 1 { "line" } else { "lines" };
+    format!("{count} unchanged {noun}")
+}
+
+/// Kind of change a hunk makes.
+#[derive(NifUnitEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HunkOp {
+    Equal,
+    Delete,
+    Insert,
+    Replace,
+}
+
+impl From<DiffTag> for HunkOp {
+    fn from(tag: DiffTag) -> Self {
+        match tag {
+            DiffTag::Equal => HunkOp::Equal,
+            DiffTag::Delete => HunkOp::Delete,
+            DiffTag::Insert => HunkOp::Insert,
+            DiffTag::Replace => HunkOp::Replace,
+        }
+    }
+}
+
+/// One diff operation over a run of lines.
+#[derive(NifMap, Clone, Debug, PartialEq, Eq)]
+pub struct DiffHunk {
+    pub op: HunkOp,
+    pub old_range: LineRange,
+    pub new_range: LineRange,
+    /// Text of the old lines in `old_range`, then of the new lines in
+    /// `new_range`, without terminators. Equal runs list their lines once.
+    pub lines: Vec<String>,
+    /// Byte ranges of the changed text in each of `lines`.
+    pub inline_spans: Vec<Vec<(usize, usize)>>,
+}
+
+/// How much changed between two revisions.
+#[derive(NifMap, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DiffStats {
+    pub lines_added: usize,
+    pub lines_removed: usize,
+    pub chars_added: usize,
+    pub chars_removed: usize,
+}
+
+/// Diff `old` against `new` with the same grouping as the HTML table: the
+/// changed operations with the configured lines of unchanged context around
+/// them, in order. Unchanged lines between groups are left out, so a gap
+/// shows as a jump in the line ranges.
+pub fn hunks(old: &str, new: &str, options: &DiffOptions) -> Vec<DiffHunk> {
+    let old = normalize(old);
+    let new = normalize(new);
+    let diff = line_diff(&old, &new, options);
+
+    diff.grouped_ops(options.context_lines)
+        .iter()
+        .flatten()
+        .map(|op| {
+            let (lines, inline_spans) = diff
+                .iter_inline_changes(op)
+                .map(|change| line_spans(&change))
+                .unzip();
+
+            DiffHunk {
+                op: op.tag().into(),
+                old_range: (op.old_range().start, op.old_range().end),
+                new_range: (op.new_range().start, op.new_range().end),
+                lines,
+                inline_spans,
+            }
+        })
+        .collect()
+}
+
+/// Count the lines and characters added and removed between `old` and
+/// `new`. Characters are counted within replaced runs of lines, so editing
+/// one word of a line counts that word rather than the whole line; line
+/// terminators are not counted. `options.timeout_ms` bounds the whole count:
+/// replaced runs reached after it has passed count every character of their
+/// lines.
+pub fn stats(old: &str, new: &str, options: &DiffOptions) -> DiffStats {
+    let deadline = Instant::now() + Duration::from_millis(options.timeout_ms);
+    let old = normalize(old);
+    let new = normalize(new);
+    let diff = TextDiff::configure()
+        .algorithm(options.algorithm.into())
+        .deadline(deadline)
+        .diff_lines(&old, &new);
+    let old_lines = diff.old_slices();
+    let new_lines = diff.new_slices();
+    let mut stats = DiffStats::default();
+
+    for op in diff.ops() {
+        let old_text = &old_lines[op.old_range()];
+        let new_text = &new_lines[op.new_range()];
+
+        match op.tag() {
+            DiffTag::Equal => continue,
+            DiffTag::Delete => stats.chars_removed += count_chars(old_text),
+            DiffTag::Insert => stats.chars_added += count_chars(new_text),
+            DiffTag::Replace if Instant::now() >= deadline => {
+                stats.chars_removed += count_chars(old_text);
+                stats.chars_added += count_chars(new_text);
+            }
+            DiffTag::Replace => {
+                let old_text = old_text.concat();
+                let new_text = new_text.concat();
+                let chars = TextDiff::configure()
+                    .algorithm(options.algorithm.into())
+                    .deadline(deadline)
+                    .diff_chars(old_text.as_str(), new_text.as_str());
+
+                for change in chars.iter_all_changes().filter(|c| c.value() != "\n") {
+                    match change.tag() {
+                        ChangeTag::Equal => {}
+                        ChangeTag::Delete => stats.chars_removed += 1,
+                        ChangeTag::Insert => stats.chars_added += 1,
+                    }
+                }
+            }
+        }
+
+        stats.lines_removed += op.old_range().len();
+        stats.lines_added += op.new_range().len();
+    }
+
+    stats
+}
+
+/// Number of characters in `lines`, not counting line terminators.
+fn count_chars(lines: &[&str]) -> usize {
+    lines
+        .iter()
+        .map(|line| trim_newline(line).chars().count())
+        .sum()
+}
+
+/// A line's text without its terminator, and the byte ranges of its
+/// emphasized (changed) pieces.
+fn line_spans(change: &InlineChange<str>) -> (String, Vec<(usize, usize)>) {
+    let mut text = String::new();
+    let mut spans = vec![];
+
+    for (emphasized, piece) in change.iter_strings_lossy() {
+        let start = text.len();
+        text.push_str(&piece);
+
+        if emphasized && change.tag() != ChangeTag::Equal {
+            spans.push((start, text.len()));
+        }
+    }
+
+    let len = trim_newline(&text).len();
+    text.truncate(len);
+
+    let spans = spans
+        .into_iter()
+        .map(|(start, end)| (start.min(len), end.min(len)))
+        .filter(|(start, end)| start < end)
+        .collect();
+
+    (text, spans)
+}
+
+/// Lines `start..end` of a document, counted from 0.
+pub type LineRange = (usize, usize);
+
+/// Overlapping changes to the same base lines, with the text each side has
+/// for them.
+#[derive(NifMap, Clone, Debug, PartialEq, Eq)]
+pub struct Conflict {
+    pub base: LineRange,
+    pub ours: LineRange,
+    pub theirs: LineRange,
+    pub base_text: String,
+    pub ours_text: String,
+    pub theirs_text: String,
+}
+
+/// A changed run of base lines and what one side replaced it with.
+#[derive(Clone, Copy)]
+struct Hunk {
+    ours: bool,
+    base: LineRange,
+    side: LineRange,
+}
+
+/// Merge the changes `ours` and `theirs` each made to `base`. Returns the
+/// merged document, or every conflict if the changes overlap.
+pub fn merge(base: &str, ours: &str, theirs: &str) -> Result<String, Vec<Conflict>> {
+    let base = normalize(base);
+    let ours = normalize(ours);
+    let theirs = normalize(theirs);
+
+    let base_lines: Vec<&str> = base.split_inclusive('\n').collect();
+    let ours_lines: Vec<&str> = ours.split_inclusive('\n').collect();
+    let theirs_lines: Vec<&str> = theirs.split_inclusive('\n').collect();
+
+    let mut hunks: Vec<Hunk> = changes(&base, &ours, true);
+    hunks.extend(changes(&base, &theirs, false));
+    hunks.sort_by_key(|hunk| (hunk.base.0, hunk.base.1, !hunk.ours));
+
+    let mut merged = String::with_capacity(base.len().max(ours.len()).max(theirs.len()));
+    let mut conflicts = vec![];
+    let mut copied_to = 0;
+    let (mut ours_delta, mut theirs_delta) = (0isize, 0isize);
+    let mut hunks = hunks.into_iter().peekable();
+
+    while let Some(first) = hunks.next() {
+        let mut cluster = vec![first];
+        let (lo, mut hi) = first.base;
+
+        while let Some(next) = hunks.next_if(|next| next.base.0 <= hi) {
+            hi = hi.max(next.base.1);
+            cluster.push(next);
+        }
+
+        let ours_range = side_range(&cluster, true, lo, hi, &mut ours_delta);
+        let theirs_range = side_range(&cluster, false, lo, hi, &mut theirs_delta);
+
+        let base_text = base_lines[lo..hi].concat();
+        let ours_text = ours_lines[ours_range.0..ours_range.1].concat();
+        let theirs_text = theirs_lines[theirs_range.0..theirs_range.1].concat();
+
+        merged.push_str(&base_lines[copied_to..lo].concat());
+        copied_to = hi;
+
+        if ours_text == theirs_text || theirs_text == base_text {
+            merged.push_str(&ours_text);
+        } else if ours_text == base_text {
+            merged.push_str(&theirs_text);
+        } else {
+            conflicts.push(Conflict {
+                base: (lo, hi),
+                ours: ours_range,
+                theirs: theirs_range,
+                base_text,
+                ours_text,
+                theirs_text,
+            });
+        }
+    }
+
+    merged.push_str(&base_lines[copied_to..].concat());
+
+    if conflicts.is_empty() {
+        Ok(merged)
+    } else {
+        Err(conflicts)
+    }
+}
+
+/// The changed runs of lines between `base` and `side`.
+fn changes(base: &str, side: &str, ours: bool) -> Vec<Hunk> {
+    let diff = line_diff(base, side, &DiffOptions::default());
+
+    diff.ops()
+        .iter()
+        .filter(|op| op.tag() != DiffTag::Equal)
+        .map(|op| Hunk {
+            ours,
+            base: (op.old_range().start, op.old_range().end),
+            side: (op.new_range().start, op.new_range().end),
+        })
+        .collect()
+}
+
+/// The lines of one side covering base lines `lo..hi` in a cluster of
+/// hunks. `delta` is how many lines that side has gained over the base
+/// before the cluster, and is advanced past it.
+fn side_range(cluster: &[Hunk], ours: bool, lo: usize, hi: usize, delta: &mut isize) -> LineRange {
+    let start = lo.checked_add_signed(*delta).unwrap_or(0);
+
+    for hunk in cluster.iter().filter(|hunk| hunk.ours == ours) {
+        *delta += (hunk.side.1 - hunk.side.0) as isize - (hunk.base.1 - hunk.base.0) as isize;
+    }
+
+    let end = hi.checked_add_signed(*delta).unwrap_or(0);
+    (start, end.max(start))
 }
 
 /// Render one line's content, wrapping the emphasized (changed) pieces in
@@ -129,17 +767,41 @@ fn push_row(
     out.push_str("</td></tr>");
 }
 
-/// Append a separator row for `count` collapsed unchanged lines.
-fn push_gap(out: &mut String, count: usize) {
-    if count == 0 {
-        return;
-    }
+/// Append one split diff table row, with the old line (if any) on the left
+/// and the new line (if any) on the right.
+fn push_split_row(out: &mut String, old: Option<&Line>, new: Option<&Line>) {
+    let changed = [old, new]
+        .into_iter()
+        .flatten()
+        .any(|line| line.tag != ChangeTag::Equal);
 
-    let noun = if count == 1 { "line" } else { "lines" };
-    let _ = write!(
-        out,
-        "<tr class=\"diff__row diff__row--gap\"><td class=\"diff__gutter\"></td><td class=\"diff__gutter\"></td><td class=\"diff__text\">{count} unchanged {noun}</td></tr>"
-    );
+    out.push_str(if changed {
+        "<tr class=\"diff__row diff__row--change\">"
+    } else {
+        "<tr class=\"diff__row\">"
+    });
+
+    push_split_side(out, old.and_then(|line| line.old_index), old, "del");
+    push_split_side(out, new.and_then(|line| line.new_index), new, "ins");
+    out.push_str("</tr>");
+}
+
+fn push_split_side(out: &mut String, index: Option<usize>, line: Option<&Line>, kind: &str) {
+    push_gutter(out, index);
+
+    match line {
+        Some(line) if line.tag == ChangeTag::Equal => {
+            let _ = write!(out, "<td class=\"diff__text\">{}</td>", line.content);
+        }
+        Some(line) => {
+            let _ = write!(
+                out,
+                "<td class=\"diff__text diff__text--{kind}\">{}</td>",
+                line.content
+            );
+        }
+        None => out.push_str("<td class=\"diff__text diff__text--empty\"></td>"),
+    }
 }
 
 /// Append a line-number gutter cell, empty when the line only exists on the
@@ -0,0 +1,286 @@
+// Image embeds generated from structured image data.
+//
+// Image mentions used to be resolved against a map of prebuilt HTML, one
+// snippet per `>>1234p` key, which had to be rendered before anyone knew
+// which of them a document would use. Instead the caller passes what it
+// knows about each mentioned image and the markup is produced here, for only
+// the mentions which appear: an embed for `t`, `s` and `p` suffixes on
+// visible images, carrying the same data as the image target template so
+// that the client can apply the viewer's filter to it, and a plain link,
+// annotated with the image's state, otherwise.
+
+use crate::{markdown, markdown_extract};
+use rustler::{NifMap, NifUnitEnum};
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// Whether an image can be shown, following its moderation state.
+#[derive(NifUnitEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageStatus {
+    Visible,
+    Merged,
+    Deleted,
+    Pending,
+}
+
+impl ImageStatus {
+    /// Text appended to links to the image.
+    fn link_suffix(self) -> &'static str {
+        match self {
+            ImageStatus::Visible => "",
+            ImageStatus::Merged => " (merged)",
+            ImageStatus::Deleted => " (deleted)",
+            ImageStatus::Pending => " (pending approval)",
+        }
+    }
+}
+
+/// A thumbnail shown for an embed suffix.
+#[derive(NifMap, Clone, Debug)]
+pub struct EmbedThumbnail {
+    /// The version actually shown, which is `full` when the image is no
+    /// larger than the version asked for.
+    pub size: String,
+    pub url: String,
+}
+
+/// What the renderer needs to know to embed an image.
+#[derive(NifMap, Clone, Debug)]
+pub struct ImageEmbed {
+    pub id: u64,
+    /// Ids of the image's tags.
+    pub tag_ids: Vec<u64>,
+    /// Names of the image's tags, each followed by the names of its aliases.
+    pub tag_aliases: Vec<String>,
+    pub score: i64,
+    pub faves: i64,
+    pub upvotes: i64,
+    pub downvotes: i64,
+    pub comment_count: i64,
+    /// Upload time, in ISO 8601.
+    pub created_at: String,
+    pub sources: Vec<String>,
+    /// URL of every version of the image, by version name.
+    pub uris: HashMap<String, String>,
+    pub width: u32,
+    pub height: u32,
+    pub aspect_ratio: f64,
+    /// Thumbnail by version asked for (`thumb_small`, `small`, `medium`).
+    pub thumbnails: HashMap<String, EmbedThumbnail>,
+    pub mime_type: String,
+    /// Alt text, used as the link title.
+    pub title: String,
+    /// Path of the viewer's current filter.
+    pub filter_path: String,
+    /// Path of the image shown in place of filtered images.
+    pub blocked_path: String,
+    pub status: ImageStatus,
+}
+
+impl ImageEmbed {
+    /// The image container's `data-` attributes, named without the prefix
+    /// and sorted by name. The client reads them to apply the viewer's
+    /// filter.
+    fn container_data(&self, size: &str) -> Vec<(&'static str, String)> {
+        let mut uris: Vec<(&String, &String)> = self.uris.iter().collect();
+        uris.sort();
+
+        let mut data = vec![
+            ("image-id", self.id.to_string()),
+            (
+                "image-tags",
+                json_array(self.tag_ids.iter().map(u64::to_string)),
+            ),
+            ("image-tag-aliases", self.tag_aliases.join(", ")),
+            ("tag-count", self.tag_ids.len().to_string()),
+            ("score", self.score.to_string()),
+            ("faves", self.faves.to_string()),
+            ("upvotes", self.upvotes.to_string()),
+            ("downvotes", self.downvotes.to_string()),
+            ("comment-count", self.comment_count.to_string()),
+            ("created-at", self.created_at.clone()),
+            (
+                "source-url",
+                self.sources.first().cloned().unwrap_or_default(),
+            ),
+            (
+                "source-urls",
+                json_array(self.sources.iter().map(|source| json_string(source))),
+            ),
+            (
+                "uris",
+                json_object(
+                    uris.into_iter()
+                        .map(|(name, url)| (json_string(name), json_string(url))),
+                ),
+            ),
+            ("width", self.width.to_string()),
+            ("height", self.height.to_string()),
+            // Debug formatting keeps the `.0` of whole numbers, as Elixir does.
+            ("aspect-ratio", format!("{:?}", self.aspect_ratio)),
+            ("size", size.to_string()),
+        ];
+        data.sort();
+        data
+    }
+}
+
+/// Thumbnail version shown for each embed suffix.
+fn version(suffix: &str) -> Option<&'static str> {
+    match suffix {
+        "p" => Some("medium"),
+        "t" => Some("small"),
+        "s" => Some("thumb_small"),
+        _ => None,
+    }
+}
+
+/// Render user-input `input`, resolving image mentions against `embeds`.
+/// Mentions of images not in `embeds` are left as text.
+pub fn to_html_with_embeds(input: &str, embeds: &[ImageEmbed]) -> String {
+    let mentions = markdown_extract::mentions(input)
+        .into_iter()
+        .map(|mention| (mention.id, mention.suffix));
+
+    markdown::to_html(input, replacements(mentions, embeds))
+}
+
+/// Build the replacement map for the given `(id, suffix)` mentions.
+pub fn replacements<I>(mentions: I, embeds: &[ImageEmbed]) -> HashMap<String, String>
+where
+    I: IntoIterator<Item = (u64, String)>,
+{
+    let embeds: HashMap<u64, &ImageEmbed> = embeds.iter().map(|e| (e.id, e)).collect();
+
+    mentions
+        .into_iter()
+        .filter_map(|(id, suffix)| {
+            let image = embeds.get(&id)?;
+            Some((format!("{id}{suffix}"), render(image, &suffix)))
+        })
+        .collect()
+}
+
+/// The markup for a mention of `image` with `suffix`.
+fn render(image: &ImageEmbed, suffix: &str) -> String {
+    let id = image.id;
+    let link_suffix = image.status.link_suffix();
+    let thumbnail = version(suffix).and_then(|version| image.thumbnails.get(version));
+
+    match (image.status, thumbnail) {
+        (ImageStatus::Visible, Some(thumbnail)) => embed(image, thumbnail),
+        (ImageStatus::Pending, _) => escape(&format!(">>{id}{suffix}{link_suffix}")),
+        _ => format!(
+            "<a href=\"/images/{id}\">{}</a>",
+            escape(&format!(">>{id}{suffix}{link_suffix}"))
+        ),
+    }
+}
+
+/// The embedded image, as the image target template renders it for embeds.
+/// Both the filter placeholder and the image start hidden; the client shows
+/// one of them once it has checked the container's data against the
+/// viewer's filter. Attributes are written in the order Phoenix's tag
+/// helpers sort them into.
+fn embed(image: &ImageEmbed, thumbnail: &EmbedThumbnail) -> String {
+    let id = image.id;
+    let url = &thumbnail.url;
+    let mut out = String::from("<div class=\"image-show-container\"");
+
+    for (name, value) in image.container_data(&thumbnail.size) {
+        let _ = write!(out, " data-{name}=\"{}\"", escape(&value));
+    }
+
+    let _ = write!(
+        out,
+        "><div class=\"block block--fixed block--warning block--no-margin image-filtered hidden\">\
+         <strong><a data-click-unfilter=\"{id}\" href=\"#\">\
+         This image is blocked by your current filter - click here to display it anyway\
+         </a></strong>\
+         <p><img data-click-unfilter=\"{id}\" height=\"250\" src=\"{}\" width=\"250\"></p>\
+         <span class=\"filter-explanation\"></span> \
+         <a class=\"filter-link\" href=\"{}\">your current filter</a>. </div>",
+        escape(&image.blocked_path),
+        escape(&image.filter_path)
+    );
+    let _ = write!(
+        out,
+        "<div class=\"image-show hidden\"><a href=\"/images/{id}\" title=\"{}\">\
+         <span class=\"imgspoiler\">",
+        escape(&image.title)
+    );
+
+    if image.mime_type == "video/webm" {
+        let _ = write!(
+            out,
+            "<video data-image-id=\"{id}\" autoplay=\"autoplay\" loop=\"loop\" muted=\"muted\" \
+             playsinline=\"playsinline\"><source src=\"{}\" type=\"video/webm\">\
+             <source src=\"{}\" type=\"video/mp4\"></video>",
+            escape(url),
+            escape(&url.replace(".webm", ".mp4"))
+        );
+    } else {
+        let _ = write!(
+            out,
+            "<picture data-image-id=\"{id}\"><img src=\"{}\"></picture>",
+            escape(url)
+        );
+    }
+
+    out.push_str("</span></a></div></div>");
+    out
+}
+
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+
+    for ch in text.chars() {
+        match ch {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(ch),
+        }
+    }
+
+    out
+}
+
+fn json_array(values: impl Iterator<Item = String>) -> String {
+    format!("[{}]", values.collect::<Vec<_>>().join(","))
+}
+
+fn json_object(entries: impl Iterator<Item = (String, String)>) -> String {
+    let entries: Vec<String> = entries
+        .map(|(name, value)| format!("{name}:{value}"))
+        .collect();
+
+    format!("{{{}}}", entries.join(","))
+}
+
+/// `text` as a JSON string, escaped as Elixir's encoder does it.
+fn json_string(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + 2);
+    out.push('"');
+
+    for ch in text.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            ch if ch < ' ' => {
+                let _ = write!(out, "\\u{:04X}", ch as u32);
+            }
+            _ => out.push(ch),
+        }
+    }
+
+    out.push('"');
+    out
+}
@@ -0,0 +1,232 @@
+// Truncated excerpts of rendered Markdown.
+//
+// Truncating the Markdown source before rendering breaks spoilers, tables
+// and links that straddle the cut. Instead, the full document is rendered
+// with the normal renderer and the resulting HTML is cut: the scanner below
+// walks tags, entities and text, counting only visible characters, and
+// closes every element that is still open at the cut point. Entities are
+// treated as a single character and are never split, and spoilered content
+// either stays inside its `<span class="spoiler">` wrapper or is dropped.
+// Image embeds are kept or cut whole and count as one character, and
+// elements hidden from view (such as an embed's filter notice) are copied
+// without counting their text.
+
+use crate::markdown;
+use rustler::NifMap;
+use std::collections::HashMap;
+
+/// Appended after the last visible character when the document was cut.
+const ELLIPSIS: &str = "\u{2026}";
+
+/// Longest entity (between `&` and `;`) treated as a single character.
+const MAX_ENTITY_LEN: usize = 32;
+
+/// Elements which never have a closing tag.
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "br", "col", "embed", "hr", "img", "input", "source", "track", "wbr",
+];
+
+/// Limits on the excerpt. A limit of zero means unlimited.
+#[derive(NifMap, Clone, Copy, Debug, Default)]
+pub struct ExcerptOptions {
+    /// Maximum number of visible characters.
+    pub max_chars: usize,
+    /// Maximum number of top-level blocks (paragraphs, tables, ...).
+    pub max_blocks: usize,
+    /// Remove spoilered content entirely instead of keeping it wrapped.
+    pub drop_spoilers: bool,
+}
+
+/// Render `input` to HTML and cut it down according to `excerpt`. Returns the
+/// excerpt and whether anything was removed.
+pub fn to_html_excerpt(
+    input: &str,
+    reps: HashMap<String, String>,
+    excerpt: ExcerptOptions,
+) -> (String, bool) {
+    truncate_html(&markdown::to_html(input, reps), excerpt)
+}
+
+/// Cut rendered HTML down according to `excerpt`.
+pub fn truncate_html(html: &str, excerpt: ExcerptOptions) -> (String, bool) {
+    let mut out = String::with_capacity(html.len().min(4096));
+    let mut open: Vec<&str> = vec![];
+    let mut chars = 0;
+    let mut blocks = 0;
+    let mut truncated = false;
+    let mut rest = html;
+
+    while !rest.is_empty() {
+        if rest.starts_with('<') {
+            let Some(end) = rest.find('>') else {
+                break;
+            };
+            let tag = &rest[..=end];
+            let name = tag_name(tag);
+
+            if tag.starts_with("</") {
+                if open.last() == Some(&name) {
+                    open.pop();
+                }
+                out.push_str(tag);
+                rest = &rest[end + 1..];
+                continue;
+            }
+
+            if open.is_empty() {
+                if excerpt.max_blocks > 0 && blocks == excerpt.max_blocks {
+                    truncated = true;
+                    break;
+                }
+                blocks += 1;
+            }
+
+            if excerpt.drop_spoilers && is_spoiler(tag) {
+                rest = skip_element(rest, name);
+                continue;
+            }
+
+            if !VOID_ELEMENTS.contains(&name) && (is_embed(tag) || is_hidden(tag)) {
+                if is_embed(tag) {
+                    if excerpt.max_chars > 0 && chars == excerpt.max_chars {
+                        truncated = true;
+                        break;
+                    }
+                    chars += 1;
+                }
+
+                let len = rest.len() - skip_element(rest, name).len();
+                out.push_str(&rest[..len]);
+                rest = &rest[len..];
+                continue;
+            }
+
+            if !VOID_ELEMENTS.contains(&name) && !tag.ends_with("/>") {
+                open.push(name);
+            }
+            out.push_str(tag);
+            rest = &rest[end + 1..];
+            continue;
+        }
+
+        // Visible character: an entity or a single (possibly multibyte) char.
+        let len = if rest.starts_with('&') {
+            rest.find(';')
+                .filter(|i| *i <= MAX_ENTITY_LEN)
+                .map_or(1, |i| i + 1)
+        } else {
+            rest.chars().next().map_or(1, char::len_utf8)
+        };
+
+        if rest.starts_with('\n') {
+            out.push('\n');
+            rest = &rest[1..];
+            continue;
+        }
+
+        if excerpt.max_chars > 0 && chars == excerpt.max_chars {
+            truncated = true;
+            break;
+        }
+
+        out.push_str(&rest[..len]);
+        rest = &rest[len..];
+        chars += 1;
+    }
+
+    if truncated {
+        let trimmed = out.trim_end().len();
+        out.truncate(trimmed);
+
+        if !open.is_empty() {
+            out.push_str(ELLIPSIS);
+        }
+    }
+
+    while let Some(name) = open.pop() {
+        out.push_str("</");
+        out.push_str(name);
+        out.push('>');
+    }
+
+    (out, truncated)
+}
+
+/// Name of the element in an opening or closing tag.
+fn tag_name(tag: &str) -> &str {
+    let tag = tag.trim_start_matches('<').trim_start_matches('/');
+    let end = tag
+        .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+        .unwrap_or(tag.len());
+
+    &tag[..end]
+}
+
+fn is_spoiler(tag: &str) -> bool {
+    tag.starts_with("<span class=\"spoiler\"")
+}
+
+/// The container of an image mention's embed.
+fn is_embed(tag: &str) -> bool {
+    has_class(tag, "image-show-container")
+}
+
+/// An element which is not displayed until a script reveals it.
+fn is_hidden(tag: &str) -> bool {
+    has_class(tag, "hidden") || attribute_names(tag).any(|name| name == "hidden")
+}
+
+fn has_class(tag: &str, class: &str) -> bool {
+    let Some(start) = tag.find(" class=\"").map(|i| i + " class=\"".len()) else {
+        return false;
+    };
+    let value = &tag[start..];
+    let value = &value[..value.find('"').unwrap_or(value.len())];
+
+    value.split_whitespace().any(|c| c == class)
+}
+
+/// Names of the attributes in an opening tag.
+fn attribute_names(tag: &str) -> impl Iterator<Item = &str> {
+    let mut quoted = false;
+
+    tag.trim_start_matches('<')
+        .split(move |c: char| {
+            if c == '"' {
+                quoted = !quoted;
+            }
+            !quoted && (c.is_whitespace() || c == '>' || c == '/')
+        })
+        .skip(1)
+        .filter_map(|attribute| attribute.split('=').next())
+        .filter(|name| !name.is_empty())
+}
+
+/// Skip past the element starting at the beginning of `html`, including any
+/// nested elements of the same name. Returns the remaining input.
+fn skip_element<'a>(html: &'a str, name: &str) -> &'a str {
+    let open = format!("<{name}");
+    let close = format!("</{name}>");
+    let mut depth = 0;
+    let mut rest = html;
+
+    while let Some(i) = rest.find('<') {
+        rest = &rest[i..];
+
+        if rest.starts_with(&close) {
+            depth -= 1;
+            rest = &rest[close.len()..];
+
+            if depth == 0 {
+                return rest;
+            }
+        } else {
+            if rest.starts_with(&open) && tag_name(rest) == name {
+                depth += 1;
+            }
+            rest = &rest[1..];
+        }
+    }
+
+    ""
+}
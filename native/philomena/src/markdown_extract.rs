@@ -0,0 +1,300 @@
+// Extraction of references from Markdown documents.
+//
+// Callers need to know what a document points at before (or instead of)
+// rendering it. Everything here walks the parsed AST rather than scanning
+// the source, so text inside code spans, code blocks, raw HTML and escaped
+// characters is never reported, exactly as the renderer would treat it.
+//
+// Positions are looked up in the source rather than computed from offsets
+// into parsed text, which can differ from what was written: smart
+// punctuation turns `'` into a three-byte curly quote and `--` into a dash,
+// and entities such as `&amp;` are decoded.
+
+use crate::markdown;
+use comrak::nodes::{AstNode, NodeValue};
+use comrak::{Arena, Options, parse_document};
+use regex::Regex;
+use rustler::{NifMap, NifUnitEnum};
+use std::collections::{HashMap, VecDeque};
+use std::sync::LazyLock;
+
+/// Image mention syntax, as resolved by the `philomena` extension.
+///
+/// The extension only resolves mentions whose key is in the replacement
+/// map, so it cannot be asked for every mention in a document and this
+/// pattern restates its syntax instead. The two may disagree where the
+/// pattern is looser: it ignores the characters around a mention, so
+/// `a>>1` and `>>1pz` are reported (as `1` and `1p`), and a mention written
+/// with entities (`&gt;&gt;1`) is matched after they are decoded.
+static MENTION: LazyLock<Regex> = LazyLock::new(|| Regex::new(r">>(\d+)([tsp])?").unwrap());
+
+/// Largest image ID which can be mentioned (the database column is an int4).
+const MAX_IMAGE_ID: u64 = 2_147_483_646;
+
+/// 1-based `(line, column)` of the first character of a reference.
+pub type Position = (usize, usize);
+
+/// A `>>1234` style image mention.
+#[derive(NifMap, Clone, Debug, PartialEq, Eq)]
+pub struct Mention {
+    pub id: u64,
+    pub suffix: String,
+    pub position: Position,
+}
+
+impl Mention {
+    /// The key the renderer looks up in its replacement map.
+    pub fn key(&self) -> String {
+        format!("{}{}", self.id, self.suffix)
+    }
+}
+
+#[derive(NifUnitEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkKind {
+    Link,
+    Image,
+    Autolink,
+}
+
+/// An outgoing URL. `url` is what the author wrote and `rewritten` is what
+/// the renderer emits after the link and image URL rewriters have run.
+#[derive(NifMap, Clone, Debug, PartialEq, Eq)]
+pub struct Link {
+    pub kind: LinkKind,
+    pub url: String,
+    pub rewritten: String,
+    pub position: Position,
+}
+
+/// Options used when walking a document for references. Escaped characters
+/// are kept in their own nodes so that `\>>1234` is never taken for a
+/// mention.
+pub(crate) fn extract_options() -> Options<'static> {
+    let mut options = markdown::common_options();
+    options.extension.replacements = Some(Default::default());
+    options.render.escaped_char_spans = true;
+    options
+}
+
+/// The lines of a document's source, for finding where text seen in a
+/// parsed literal was written.
+pub(crate) struct Source<'s> {
+    lines: Vec<&'s str>,
+}
+
+impl<'s> Source<'s> {
+    pub(crate) fn new(input: &'s str) -> Self {
+        Source {
+            lines: input.lines().collect(),
+        }
+    }
+
+    /// The source from `position` to the end of its line.
+    pub(crate) fn at(&self, position: Position) -> &'s str {
+        self.lines
+            .get(position.0.wrapping_sub(1))
+            .and_then(|line| line.get(position.1.saturating_sub(1)..))
+            .unwrap_or("")
+    }
+
+    /// A cursor over the matches of `pattern` written in a literal spanning
+    /// `start` to `end`. The source of the literal is scanned once, here.
+    pub(crate) fn cursor(&self, pattern: &Regex, start: Position, end: Position) -> Cursor {
+        let mut written: HashMap<String, VecDeque<Position>> = HashMap::new();
+
+        for line in start.0.max(1)..=end.0.min(self.lines.len()) {
+            let text = self.lines[line - 1];
+            let from = if line == start.0 {
+                start.1.saturating_sub(1)
+            } else {
+                0
+            };
+            let to = if line == end.0 {
+                end.1.min(text.len())
+            } else {
+                text.len()
+            };
+            let Some(span) = text.get(from..to) else {
+                continue;
+            };
+
+            for found in pattern.find_iter(span) {
+                written
+                    .entry(found.as_str().to_string())
+                    .or_default()
+                    .push_back((line, from + found.start() + 1));
+            }
+        }
+
+        Cursor {
+            start,
+            last: (0, 0),
+            written,
+        }
+    }
+}
+
+/// Where the matches of a pattern in one parsed literal were written,
+/// consumed in document order. Every match is looked at once, so walking
+/// all the matches of a literal takes time linear in its length.
+pub(crate) struct Cursor {
+    start: Position,
+    last: Position,
+    written: HashMap<String, VecDeque<Position>>,
+}
+
+impl Cursor {
+    /// Where the next match reading `needle` was written, after the one
+    /// returned last. Falls back to the start of the literal when it cannot
+    /// be found, as with text written using entities.
+    pub(crate) fn next(&mut self, needle: &str) -> Position {
+        let Some(positions) = self.written.get_mut(needle) else {
+            return self.start;
+        };
+
+        while let Some(position) = positions.pop_front() {
+            if position > self.last {
+                self.last = position;
+                return position;
+            }
+        }
+
+        self.start
+    }
+}
+
+/// Call `f` with each text literal in the document which the renderer could
+/// turn into a reference, along with the start and end of its source.
+pub(crate) fn each_text<'a, F>(root: &'a AstNode<'a>, mut f: F)
+where
+    F: FnMut(&str, Position, Position),
+{
+    for node in root.descendants() {
+        let ast = node.data.borrow();
+
+        if let NodeValue::Text(ref text) = ast.value {
+            let skipped = node.ancestors().skip(1).any(|a| {
+                matches!(
+                    a.data.borrow().value,
+                    NodeValue::Escaped | NodeValue::Image(..)
+                )
+            });
+
+            if !skipped {
+                f(
+                    text,
+                    (ast.sourcepos.start.line, ast.sourcepos.start.column),
+                    (ast.sourcepos.end.line, ast.sourcepos.end.column),
+                );
+            }
+        }
+    }
+}
+
+/// Return every image mention in `input`, in document order.
+pub fn mentions(input: &str) -> Vec<Mention> {
+    let options = extract_options();
+    let arena = Arena::new();
+    let root = parse_document(&arena, input, &options);
+    let source = Source::new(input);
+    let mut found = vec![];
+
+    each_text(root, |text, start, end| {
+        let mut cursor = source.cursor(&MENTION, start, end);
+
+        for caps in MENTION.captures_iter(text) {
+            let Some(id) = caps[1].parse::<u64>().ok().filter(|id| *id <= MAX_IMAGE_ID) else {
+                continue;
+            };
+
+            let whole = caps.get(0).unwrap();
+
+            found.push(Mention {
+                id,
+                suffix: caps.get(2).map_or("", |m| m.as_str()).into(),
+                position: cursor.next(whole.as_str()),
+            });
+        }
+    });
+
+    found
+}
+
+/// Return every link, autolink and image URL in `input`, in document order.
+pub fn links(input: &str) -> Vec<Link> {
+    links_with_options(input, &extract_options())
+}
+
+/// As [`links`], resolving URLs with the rewriters installed in `options`.
+pub fn links_with_options(input: &str, options: &Options) -> Vec<Link> {
+    let arena = Arena::new();
+    let root = parse_document(&arena, input, options);
+    let source = Source::new(input);
+    let mut found = vec![];
+
+    for node in root.descendants() {
+        let ast = node.data.borrow();
+        let position = (ast.sourcepos.start.line, ast.sourcepos.start.column);
+
+        let (kind, url, rewriter) = match ast.value {
+            NodeValue::Link(ref link) if is_autolink(node, &link.url, source.at(position)) => (
+                LinkKind::Autolink,
+                link.url.to_string(),
+                &options.extension.link_url_rewriter,
+            ),
+            NodeValue::Link(ref link) => (
+                LinkKind::Link,
+                link.url.to_string(),
+                &options.extension.link_url_rewriter,
+            ),
+            NodeValue::Image(ref link) => (
+                LinkKind::Image,
+                link.url.to_string(),
+                &options.extension.image_url_rewriter,
+            ),
+            _ => continue,
+        };
+
+        let rewritten = match rewriter {
+            Some(rewriter) => rewriter.to_html(&url),
+            None => url.clone(),
+        };
+
+        found.push(Link {
+            kind,
+            url,
+            rewritten,
+            position,
+        });
+    }
+
+    found
+}
+
+/// An autolink's only child is its own URL (possibly without the scheme
+/// the autolinker added), and unlike an explicit `[url](url)` its `source`
+/// does not open with a bracket.
+fn is_autolink<'a>(node: &'a AstNode<'a>, url: &str, source: &str) -> bool {
+    if source.starts_with('[') {
+        return false;
+    }
+
+    let Some(child) = node.first_child() else {
+        return false;
+    };
+
+    if child.next_sibling().is_some() {
+        return false;
+    }
+
+    match child.data.borrow().value {
+        NodeValue::Text(ref text) => {
+            let text: &str = text;
+
+            url == text
+                || url.strip_prefix("http://") == Some(text)
+                || url.strip_prefix("mailto:") == Some(text)
+        }
+        _ => false,
+    }
+}
@@ -0,0 +1,73 @@
+// Canonical re-formatting of Markdown sources.
+//
+// Sources are parsed with the renderer's own options and written back out
+// with comrak's CommonMark formatter, which settles on one list marker, one
+// emphasis style and escapes special characters consistently. Line endings
+// are normalized and trailing whitespace trimmed afterwards. The formatter
+// does not know every Philomena extension equally well, so the result is
+// only used when it renders to exactly the same HTML as the original;
+// otherwise the source is returned with just its line endings and trailing
+// whitespace cleaned up (when even that is safe) or untouched.
+
+use crate::markdown;
+use comrak::options::ListStyleType;
+use comrak::{Arena, Options, parse_document};
+
+/// Re-emit `input` as normalized Markdown which renders identically.
+pub fn format(input: &str) -> String {
+    let options = render_options();
+    let expected = comrak::markdown_to_html(input, &options);
+
+    [reformat(input, &options), Some(tidy(input))]
+        .into_iter()
+        .flatten()
+        .find(|candidate| comrak::markdown_to_html(candidate, &options) == expected)
+        .unwrap_or_else(|| input.to_string())
+}
+
+/// The common options without replacements, so mentions render the same
+/// however they are later expanded.
+fn render_options() -> Options<'static> {
+    let mut options = markdown::common_options();
+    options.extension.replacements = Some(Default::default());
+    options
+}
+
+/// `input` written back out by the CommonMark formatter. Smart punctuation
+/// is turned off while parsing, since the formatter would otherwise write
+/// the curly quotes and dashes it produces back into the source; the
+/// rendered output is the same either way.
+fn reformat(input: &str, options: &Options) -> Option<String> {
+    let mut options = options.clone();
+    options.parse.smart = false;
+    options.render.list_style = ListStyleType::Dash;
+    options.render.width = 0;
+
+    let arena = Arena::new();
+    let root = parse_document(&arena, input, &options);
+
+    let mut output = String::new();
+    comrak::format_commonmark(root, &options, &mut output).ok()?;
+
+    Some(tidy(&output))
+}
+
+/// Normalize line endings, trim trailing whitespace, and end with exactly
+/// one newline (or nothing, for an empty document).
+fn tidy(input: &str) -> String {
+    let input = input.replace("\r\n", "\n").replace('\r', "\n");
+    let mut output = input
+        .lines()
+        .map(str::trim_end)
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let trimmed = output.trim_end_matches('\n').len();
+    output.truncate(trimmed);
+
+    if !output.is_empty() {
+        output.push('\n');
+    }
+
+    output
+}
@@ -0,0 +1,131 @@
+// Server-side syntax highlighting for fenced code blocks.
+//
+// Highlighting is opt-in per render and limited to an allowlist of
+// languages. Tokens are emitted as `<span class="hl-...">` elements so the
+// site stylesheet decides the colours; no inline styles are produced. Blocks
+// in other languages, blocks without a language, and blocks which would take
+// the render past `MAX_HIGHLIGHT_BYTES` of highlighted code fall back to the
+// same escaped output the renderer produces without highlighting, so a huge
+// pasted log, or many smaller ones, costs no more than it did before.
+
+use comrak::adapters::SyntaxHighlighterAdapter;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+use std::sync::LazyLock;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use syntect::html::{ClassStyle, ClassedHTMLGenerator};
+use syntect::parsing::SyntaxSet;
+use syntect::util::LinesWithEndings;
+
+/// Most code, in bytes, which will be highlighted in one render.
+pub const MAX_HIGHLIGHT_BYTES: usize = 32 * 1024;
+
+/// Prefix for every token class, to keep them out of the site's namespace.
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+static SYNTAXES: LazyLock<SyntaxSet> = LazyLock::new(SyntaxSet::load_defaults_newlines);
+
+/// Highlights the code blocks of a single render.
+pub struct Highlighter {
+    languages: Vec<String>,
+    /// Bytes of code highlighted so far.
+    used: AtomicUsize,
+}
+
+impl Highlighter {
+    /// Highlight code blocks whose info string names one of `languages`.
+    pub fn new(languages: Vec<String>) -> Self {
+        Self {
+            languages: languages.into_iter().map(|l| l.to_lowercase()).collect(),
+            used: AtomicUsize::new(0),
+        }
+    }
+
+    fn highlight(&self, lang: Option<&str>, code: &str) -> Option<String> {
+        let lang = lang?.to_lowercase();
+        let used = self.used.load(Ordering::Relaxed) + code.len();
+
+        if used > MAX_HIGHLIGHT_BYTES || !self.languages.contains(&lang) {
+            return None;
+        }
+
+        let syntax = SYNTAXES.find_syntax_by_token(&lang)?;
+        let mut generator =
+            ClassedHTMLGenerator::new_with_class_style(syntax, &SYNTAXES, CLASS_STYLE);
+
+        for line in LinesWithEndings::from(code) {
+            generator
+                .parse_html_for_line_which_includes_newline(line)
+                .ok()?;
+        }
+
+        self.used.store(used, Ordering::Relaxed);
+
+        Some(generator.finalize())
+    }
+}
+
+impl SyntaxHighlighterAdapter for Highlighter {
+    fn write_highlighted(
+        &self,
+        output: &mut dyn Write,
+        lang: Option<&str>,
+        code: &str,
+    ) -> fmt::Result {
+        match self.highlight(lang, code) {
+            Some(html) => output.write_str(&html),
+            None => escape(output, code),
+        }
+    }
+
+    fn write_pre_tag(
+        &self,
+        output: &mut dyn Write,
+        attributes: HashMap<&'static str, Cow<'_, str>>,
+    ) -> fmt::Result {
+        write_opening_tag(output, "pre", attributes)
+    }
+
+    fn write_code_tag(
+        &self,
+        output: &mut dyn Write,
+        attributes: HashMap<&'static str, Cow<'_, str>>,
+    ) -> fmt::Result {
+        write_opening_tag(output, "code", attributes)
+    }
+}
+
+/// Write an opening tag with its attributes in a stable order.
+fn write_opening_tag(
+    output: &mut dyn Write,
+    tag: &str,
+    attributes: HashMap<&'static str, Cow<'_, str>>,
+) -> fmt::Result {
+    let mut attributes: Vec<_> = attributes.into_iter().collect();
+    attributes.sort_by_key(|(name, _)| *name);
+
+    write!(output, "<{tag}")?;
+
+    for (name, value) in attributes {
+        write!(output, " {name}=\"")?;
+        escape(output, &value)?;
+        output.write_char('"')?;
+    }
+
+    output.write_char('>')
+}
+
+fn escape(output: &mut dyn Write, text: &str) -> fmt::Result {
+    for ch in text.chars() {
+        match ch {
+            '&' => output.write_str("&amp;")?,
+            '<' => output.write_str("&lt;")?,
+            '>' => output.write_str("&gt;")?,
+            '"' => output.write_str("&quot;")?,
+            _ => output.write_char(ch)?,
+        }
+    }
+
+    Ok(())
+}
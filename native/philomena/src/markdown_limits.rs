@@ -0,0 +1,128 @@
+// Rendering with hard resource limits.
+//
+// Rendering runs on a dirty scheduler, and a hostile document can make it
+// expensive: quotes or lists nested thousands deep, tables with thousands of
+// columns, or a handful of mentions whose replacements expand into megabytes
+// of HTML. Input size is checked before parsing, nesting depth and table
+// cells are counted on the parsed tree before anything is rendered, and the
+// renderer writes into a buffer which refuses to grow past the output limit,
+// so rendering stops as soon as the limit is reached. Applying the link
+// policy afterwards adds attributes to off-site links, so the finished HTML
+// is measured against the limit again.
+
+use crate::markdown;
+use comrak::nodes::{AstNode, NodeValue};
+use comrak::{Arena, parse_document};
+use rustler::{NifMap, NifUnitEnum};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Maximums for a single render. A limit of 0 disables that check.
+#[derive(NifMap, Clone, Copy, Debug, Default)]
+pub struct Limits {
+    pub max_input_bytes: usize,
+    pub max_output_bytes: usize,
+    pub max_depth: usize,
+    pub max_table_cells: usize,
+}
+
+/// The limit a render exceeded.
+#[derive(NifUnitEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LimitError {
+    InputTooLarge,
+    OutputTooLarge,
+    NestingTooDeep,
+    TooManyTableCells,
+}
+
+/// Render user-input `input` like `markdown::to_html`, failing if any of
+/// `limits` is exceeded.
+pub fn to_html_limited(
+    input: &str,
+    reps: HashMap<String, String>,
+    limits: &Limits,
+) -> Result<String, LimitError> {
+    if exceeds(input.len(), limits.max_input_bytes) {
+        return Err(LimitError::InputTooLarge);
+    }
+
+    let mut options = markdown::common_options();
+    options.extension.replacements = Some(reps);
+
+    let arena = Arena::new();
+    let root = parse_document(&arena, input, &options);
+
+    let (depth, cells) = measure(root);
+
+    if exceeds(depth, limits.max_depth) {
+        return Err(LimitError::NestingTooDeep);
+    }
+
+    if exceeds(cells, limits.max_table_cells) {
+        return Err(LimitError::TooManyTableCells);
+    }
+
+    let mut output = BoundedWriter::new(limits.max_output_bytes);
+
+    if comrak::format_html(root, &options, &mut output).is_err() {
+        return Err(LimitError::OutputTooLarge);
+    }
+
+    let html = markdown::finish(output.buffer);
+
+    if exceeds(html.len(), limits.max_output_bytes) {
+        return Err(LimitError::OutputTooLarge);
+    }
+
+    Ok(html)
+}
+
+fn exceeds(value: usize, limit: usize) -> bool {
+    limit > 0 && value > limit
+}
+
+/// The deepest nesting of nodes below `root`, and the number of table
+/// cells in the document.
+pub(crate) fn measure<'a>(root: &'a AstNode<'a>) -> (usize, usize) {
+    let mut stack = vec![(root, 0)];
+    let mut depth = 0;
+    let mut cells = 0;
+
+    while let Some((node, level)) = stack.pop() {
+        depth = depth.max(level);
+
+        if matches!(node.data.borrow().value, NodeValue::TableCell) {
+            cells += 1;
+        }
+
+        stack.extend(node.children().map(|child| (child, level + 1)));
+    }
+
+    (depth, cells)
+}
+
+/// A `String` writer which fails instead of growing past `max` bytes.
+struct BoundedWriter {
+    buffer: String,
+    max: usize,
+}
+
+impl BoundedWriter {
+    fn new(max: usize) -> Self {
+        Self {
+            buffer: String::new(),
+            max,
+        }
+    }
+}
+
+impl fmt::Write for BoundedWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if exceeds(self.buffer.len() + s.len(), self.max) {
+            return Err(fmt::Error);
+        }
+
+        self.buffer.push_str(s);
+        Ok(())
+    }
+}
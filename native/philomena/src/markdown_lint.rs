@@ -0,0 +1,163 @@
+// Diagnostics for common Markdown mistakes.
+//
+// The renderer is deliberately forgiving: an unmatched `||` is left as
+// literal pipes, an empty link is printed as its source, and a table whose
+// delimiter row does not line up with its header is just a paragraph. None
+// of that is an error, but it is rarely what the author meant. The linter
+// parses the document with the renderer's rules and reports these cases
+// with their source range, so the comment form can point them out before
+// the post is submitted.
+
+use crate::markdown_extract::{self, Position};
+use comrak::nodes::{AstNode, NodeValue};
+use comrak::{Arena, parse_document};
+use regex::Regex;
+use rustler::{NifMap, NifUnitEnum};
+use std::collections::HashSet;
+use std::sync::LazyLock;
+
+/// A table delimiter row, e.g. `--- | :---:`.
+static DELIMITER_ROW: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\s*\|?\s*:?-+:?\s*(\|\s*:?-+:?\s*)*\|?\s*$").unwrap());
+
+/// A run of pipes long enough to open or close a spoiler.
+static SPOILER_RUN: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\|{2,}").unwrap());
+
+#[derive(NifUnitEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WarningKind {
+    UnclosedSpoiler,
+    EmptyLink,
+    BrokenMention,
+    BrokenTable,
+}
+
+/// A single diagnostic. `start` and `end` are inclusive 1-based
+/// `(line, column)` positions.
+#[derive(NifMap, Clone, Debug, PartialEq, Eq)]
+pub struct Warning {
+    pub kind: WarningKind,
+    pub start: Position,
+    pub end: Position,
+    pub message: String,
+}
+
+/// Lint `input`. When `existing_images` is given, image mentions of any
+/// other ID are reported as broken.
+pub fn lint(input: &str, existing_images: Option<Vec<u64>>) -> Vec<Warning> {
+    let options = markdown_extract::extract_options();
+    let arena = Arena::new();
+    let root = parse_document(&arena, input, &options);
+    let mut warnings = vec![];
+
+    unclosed_spoilers(root, input, &mut warnings);
+    empty_links(root, &mut warnings);
+    broken_tables(root, input, &mut warnings);
+
+    if let Some(existing) = existing_images {
+        let existing: HashSet<u64> = existing.into_iter().collect();
+
+        for mention in markdown_extract::mentions(input) {
+            if existing.contains(&mention.id) {
+                continue;
+            }
+
+            let (line, column) = mention.position;
+            let key = mention.key();
+
+            warnings.push(Warning {
+                kind: WarningKind::BrokenMention,
+                start: (line, column),
+                end: (line, column + key.len() + 1),
+                message: format!("Image >>{key} does not exist"),
+            });
+        }
+    }
+
+    warnings.sort_by_key(|w| w.start);
+    warnings
+}
+
+/// Any `||` left in text after parsing did not pair up into a spoiler.
+fn unclosed_spoilers<'a>(root: &'a AstNode<'a>, input: &str, warnings: &mut Vec<Warning>) {
+    let source = markdown_extract::Source::new(input);
+
+    markdown_extract::each_text(root, |text, node_start, node_end| {
+        let mut cursor = source.cursor(&SPOILER_RUN, node_start, node_end);
+
+        for found in SPOILER_RUN.find_iter(text) {
+            let run = found.as_str().len();
+            let (line, column) = cursor.next(found.as_str());
+
+            warnings.push(Warning {
+                kind: WarningKind::UnclosedSpoiler,
+                start: (line, column),
+                end: (line, column + run - 1),
+                message: "This spoiler is never closed".into(),
+            });
+        }
+    });
+}
+
+/// Links with no visible text are printed as source by the renderer.
+fn empty_links<'a>(root: &'a AstNode<'a>, warnings: &mut Vec<Warning>) {
+    for node in root.descendants() {
+        let ast = node.data.borrow();
+
+        if !matches!(ast.value, NodeValue::Link(..)) || has_content(node) {
+            continue;
+        }
+
+        warnings.push(Warning {
+            kind: WarningKind::EmptyLink,
+            start: (ast.sourcepos.start.line, ast.sourcepos.start.column),
+            end: (ast.sourcepos.end.line, ast.sourcepos.end.column),
+            message: "This link has no text and will not be shown as a link".into(),
+        });
+    }
+}
+
+fn has_content<'a>(node: &'a AstNode<'a>) -> bool {
+    node.descendants()
+        .skip(1)
+        .any(|n| match n.data.borrow().value {
+            NodeValue::Text(ref text) => !text.trim().is_empty(),
+            NodeValue::Code(..) | NodeValue::Image(..) | NodeValue::HtmlInline(..) => true,
+            _ => false,
+        })
+}
+
+/// A delimiter row directly below a line with pipes, still inside a
+/// paragraph, means the table failed to parse. The source lines are checked
+/// rather than the parsed text, since smart punctuation turns `---` into
+/// dashes.
+fn broken_tables<'a>(root: &'a AstNode<'a>, input: &str, warnings: &mut Vec<Warning>) {
+    let source: Vec<&str> = input.lines().collect();
+
+    for paragraph in root.descendants() {
+        let ast = paragraph.data.borrow();
+
+        if !matches!(ast.value, NodeValue::Paragraph) {
+            continue;
+        }
+
+        let first = ast.sourcepos.start.line.max(1);
+        let last = ast.sourcepos.end.line.min(source.len());
+
+        for line in (first + 1)..=last {
+            let header = source[line - 2];
+            let delimiter = source[line - 1];
+
+            if header.contains('|') && delimiter.contains('|') && DELIMITER_ROW.is_match(delimiter)
+            {
+                let indent = delimiter.len() - delimiter.trim_start().len();
+
+                warnings.push(Warning {
+                    kind: WarningKind::BrokenTable,
+                    start: (line, indent + 1),
+                    end: (line, delimiter.trim_end().len()),
+                    message: "This table's delimiter row does not match its header row, so the table will not be shown".into(),
+                });
+            }
+        }
+    }
+}
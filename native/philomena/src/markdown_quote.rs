@@ -0,0 +1,128 @@
+// Quote-reply generation.
+//
+// Prefixing every line of a post with `>` is not enough to quote it:
+// greentext lines (`>implying`) have no space after the marker and turn into
+// something else, quotes nest without bound as a thread replies to itself,
+// and `>>1234p` embeds are re-embedded in every reply. The quoter below
+// prefixes each line with a proper `> ` marker, drops lines which would be
+// nested deeper than the configured depth, and turns image embeds into
+// plain `>>1234` links. Mentions are found with the renderer's own rules,
+// so text in code is left alone.
+
+use crate::markdown_extract;
+
+/// Characters with meaning in Markdown, escaped in the author's name.
+const SPECIAL_CHARS: &[char] = &[
+    '*', '_', '[', ']', '(', ')', '^', '`', '%', '\\', '~', '<', '>', '#', '|',
+];
+
+/// Quote `source` for a reply attributed to `author` at `link`. Lines that
+/// would end up nested more than `max_depth` quotes deep are removed.
+pub fn quote(source: &str, author: &str, link: &str, max_depth: usize) -> String {
+    let source = source.replace("\r\n", "\n");
+    let mut lines: Vec<String> = source.lines().map(str::to_string).collect();
+
+    unembed_mentions(&source, &mut lines);
+
+    let mut out = String::new();
+    out.push('[');
+    escape_into(&mut out, author);
+    out.push_str("](");
+    out.push_str(link);
+    out.push_str(")\n");
+
+    let mut in_fence = false;
+    let mut last_blank = true;
+    let mut quoted = 0;
+
+    for line in &lines {
+        let depth = if in_fence { 0 } else { quote_depth(line) };
+
+        if is_fence(line) {
+            in_fence = !in_fence;
+        }
+
+        if depth + 1 > max_depth {
+            continue;
+        }
+
+        let blank = line.trim().is_empty();
+        if blank && last_blank {
+            continue;
+        }
+
+        if blank {
+            out.push_str(">\n");
+        } else {
+            out.push_str("> ");
+            out.push_str(line.trim_end());
+            out.push('\n');
+        }
+
+        last_blank = blank;
+        quoted += 1;
+    }
+
+    if quoted > 0 && last_blank {
+        // Drop the trailing empty quote line.
+        out.truncate(out.len() - ">\n".len());
+    }
+
+    out.push('\n');
+    out
+}
+
+/// Remove the size suffix from each `>>1234p` style embed, leaving a link.
+fn unembed_mentions(source: &str, lines: &mut [String]) {
+    for mention in markdown_extract::mentions(source).into_iter().rev() {
+        if mention.suffix.is_empty() {
+            continue;
+        }
+
+        let (line, column) = mention.position;
+        let Some(text) = lines.get_mut(line - 1) else {
+            continue;
+        };
+
+        let start = column - 1;
+        let end = start + ">>".len() + mention.key().len();
+        let expected = format!(">>{}", mention.key());
+
+        if text.get(start..end) == Some(expected.as_str()) {
+            text.replace_range(end - mention.suffix.len()..end, "");
+        }
+    }
+}
+
+/// Number of block quote markers (`>` followed by a space or the end of the
+/// line) at the start of `line`. A `>` directly followed by text is
+/// greentext, not a quote.
+fn quote_depth(line: &str) -> usize {
+    let mut rest = line.trim_start_matches(' ');
+    let mut depth = 0;
+
+    while let Some(after) = rest.strip_prefix('>') {
+        if !(after.is_empty() || after.starts_with(' ')) {
+            break;
+        }
+
+        depth += 1;
+        rest = after.trim_start_matches(' ');
+    }
+
+    depth
+}
+
+fn is_fence(line: &str) -> bool {
+    let line = line.trim_start();
+    line.starts_with("```") || line.starts_with("~~~")
+}
+
+fn escape_into(out: &mut String, text: &str) {
+    for ch in text.chars() {
+        if SPECIAL_CHARS.contains(&ch) {
+            out.push('\\');
+        }
+        out.push(ch);
+    }
+}
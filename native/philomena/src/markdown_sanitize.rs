@@ -0,0 +1,136 @@
+// Rendering for semi-trusted documents.
+//
+// Site notices and forum descriptions are edited by staff who need some raw
+// HTML, but not the unrestricted pass-through the static page renderer
+// gives admins. Here raw HTML is allowed through comrak and the result is
+// cleaned against a caller-supplied allowlist of tags, attributes and URL
+// schemes. Every `<img src>` which survives is sent through the camo proxy,
+// as Markdown images are.
+//
+// Links are checked while cleaning, where each attribute is seen on its own,
+// rather than by matching the cleaned markup: every anchor gets the off-site
+// rel, and links to blocklisted hosts lose their href.
+//
+// Image mention replacements are generated by the site itself and contain
+// markup the allowlist would strip, so they are rendered as random
+// placeholders, and only swapped for the real markup after cleaning.
+
+use crate::links::{self, LinkPolicy};
+use crate::{camo, domains, markdown};
+use ammonia::{Builder, UrlRelative};
+use regex::{Captures, Regex};
+use ring::rand::{SecureRandom, SystemRandom};
+use rustler::NifMap;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+
+/// Elements removed together with their contents, rather than unwrapped.
+const CLEAN_CONTENT_TAGS: &[&str] = &["script", "style"];
+
+/// What the sanitizer lets through.
+#[derive(NifMap, Clone, Debug, Default)]
+pub struct SanitizePolicy {
+    /// Allowed element names.
+    pub tags: Vec<String>,
+    /// Allowed attributes, by element name.
+    pub attributes: HashMap<String, Vec<String>>,
+    /// Attributes allowed on any element.
+    pub generic_attributes: Vec<String>,
+    /// Allowed schemes for absolute URLs. Relative URLs are always allowed.
+    pub url_schemes: Vec<String>,
+}
+
+/// Render semi-trusted `input`, allowing raw HTML but only what `policy`
+/// permits.
+pub fn to_html_sanitized(
+    input: &str,
+    reps: HashMap<String, String>,
+    policy: &SanitizePolicy,
+) -> String {
+    let nonce = nonce();
+    let placeholder = |n: usize| format!("philomena{nonce}r{n}r");
+    let values: Vec<String> = reps.values().cloned().collect();
+    let placeholders = reps
+        .into_keys()
+        .enumerate()
+        .map(|(n, key)| (key, placeholder(n)))
+        .collect();
+
+    let mut options = markdown::unsafe_options();
+    options.extension.replacements = Some(placeholders);
+
+    let html = comrak::markdown_to_html(input, &options);
+    let clean = sanitize(&html, policy, &links::get());
+
+    let restore = Regex::new(&format!(r"philomena{nonce}r(\d+)r")).unwrap();
+    let restored = restore.replace_all(&clean, |caps: &Captures| {
+        caps[1]
+            .parse::<usize>()
+            .ok()
+            .and_then(|n| values.get(n))
+            .cloned()
+            .unwrap_or_default()
+    });
+
+    markdown::finish(restored.into_owned())
+}
+
+/// Clean `html` against `policy`, removing the targets of links to hosts
+/// `link_policy` blocks.
+pub fn sanitize(html: &str, policy: &SanitizePolicy, link_policy: &LinkPolicy) -> String {
+    let tags: HashSet<&str> = policy
+        .tags
+        .iter()
+        .map(String::as_str)
+        .filter(|tag| !CLEAN_CONTENT_TAGS.contains(tag))
+        .collect();
+    let tag_attributes: HashMap<&str, HashSet<&str>> = policy
+        .attributes
+        .iter()
+        .map(|(tag, attributes)| {
+            (
+                tag.as_str(),
+                attributes.iter().map(String::as_str).collect(),
+            )
+        })
+        .collect();
+
+    let domains = domains::get();
+    let link_policy = link_policy.clone();
+
+    Builder::empty()
+        .tags(tags)
+        .tag_attributes(tag_attributes)
+        .generic_attributes(
+            policy
+                .generic_attributes
+                .iter()
+                .map(String::as_str)
+                .collect(),
+        )
+        .url_schemes(policy.url_schemes.iter().map(String::as_str).collect())
+        .url_relative(UrlRelative::PassThrough)
+        .link_rel(Some(links::EXTERNAL_REL))
+        .clean_content_tags(CLEAN_CONTENT_TAGS.iter().copied().collect())
+        .attribute_filter(
+            move |element, attribute, value| match (element, attribute) {
+                ("img", "src") => Some(Cow::Owned(camo::image_url(value))),
+                ("a", "href") if links::is_blocked_link(value, domains.as_ref(), &link_policy) => {
+                    None
+                }
+                _ => Some(Cow::Borrowed(value)),
+            },
+        )
+        .clean(html)
+        .to_string()
+}
+
+/// A random token which user input cannot predict.
+fn nonce() -> String {
+    let mut bytes = [0u8; 12];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .expect("system random number generator failed");
+
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
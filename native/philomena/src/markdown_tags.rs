@@ -0,0 +1,235 @@
+// `{{tag name}}` links to tag pages.
+//
+// Tag references are found in the parsed document's text nodes, so braces in
+// code or escapes are left alone, and become links to `/tags/<slug>` with
+// the slug encoded exactly as `Philomena.Slug.slug/1` does. Names are
+// normalized the way the tag parser starts out (lowercased, curly quotes
+// straightened, whitespace collapsed); the caller is expected to look the
+// returned names up and may pass back a state for each, which is rendered as
+// a modifier class on the link.
+
+use crate::markdown;
+use crate::markdown_extract;
+use crate::markdown_users;
+use comrak::nodes::{AstNode, NodeLink, NodeValue};
+use comrak::{Arena, parse_document};
+use regex::{Captures, Regex};
+use rustler::NifUnitEnum;
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+/// A tag reference inside a single text node.
+static TAG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\{\{([^{}\n]+)\}\}").unwrap());
+
+/// Opening tag of a link to a tag page, as the renderer writes it.
+static TAG_ANCHOR: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"<a href="(/tags/[^"]*)""#).unwrap());
+
+/// Longest tag name, in bytes, which is linked.
+const MAX_NAME_BYTES: usize = 255;
+
+/// How a referenced tag should be styled.
+#[derive(NifUnitEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TagState {
+    Exists,
+    Aliased,
+    Missing,
+}
+
+impl TagState {
+    fn class(self) -> &'static str {
+        match self {
+            TagState::Exists => "tag-link",
+            TagState::Aliased => "tag-link tag-link--aliased",
+            TagState::Missing => "tag-link tag-link--missing",
+        }
+    }
+}
+
+/// Return the normalized name of every tag referenced by `input`, in order
+/// of first reference.
+pub fn tags(input: &str) -> Vec<String> {
+    let options = markdown_extract::extract_options();
+    let arena = Arena::new();
+    let root = parse_document(&arena, input, &options);
+    let mut found: Vec<String> = vec![];
+
+    for node in root
+        .descendants()
+        .filter(|node| markdown_users::linkable(node))
+    {
+        if let NodeValue::Text(ref text) = node.data.borrow().value {
+            for caps in TAG.captures_iter(text) {
+                if let Some(name) = normalize(&caps[1])
+                    && !found.contains(&name)
+                {
+                    found.push(name);
+                }
+            }
+        }
+    }
+
+    found
+}
+
+/// Render user-input `input`, linking each `{{tag}}` to its tag page. When
+/// `states` is given, links carry a class for the state of their tag.
+/// Returns the HTML and the referenced tag names.
+pub fn to_html_with_tags(
+    input: &str,
+    reps: HashMap<String, String>,
+    states: Option<HashMap<String, TagState>>,
+) -> (String, Vec<String>) {
+    let mut options = markdown::common_options();
+    options.extension.replacements = Some(reps);
+
+    let mut parse_options = options.clone();
+    parse_options.render.escaped_char_spans = true;
+
+    let arena = Arena::new();
+    let root = parse_document(&arena, input, &parse_options);
+    let found = link_tags(root, |value| arena.alloc(value.into()));
+
+    let html = markdown::format_html(root, &options);
+    let html = match states {
+        Some(states) => style(&html, &found, &states),
+        None => html,
+    };
+
+    (markdown::finish(html), found)
+}
+
+/// Replace each tag reference in `root` with a link, allocating new nodes
+/// with `alloc`. Returns the tags referenced.
+fn link_tags<'a, F>(root: &'a AstNode<'a>, alloc: F) -> Vec<String>
+where
+    F: Fn(NodeValue) -> &'a AstNode<'a>,
+{
+    let mut found: Vec<String> = vec![];
+    let nodes: Vec<_> = root
+        .descendants()
+        .filter(|node| markdown_users::linkable(node))
+        .collect();
+
+    for node in nodes {
+        let text = match node.data.borrow().value {
+            NodeValue::Text(ref text) => text.to_string(),
+            _ => continue,
+        };
+
+        let mut start = 0;
+        let mut replaced = false;
+
+        for caps in TAG.captures_iter(&text) {
+            let Some(name) = normalize(&caps[1]) else {
+                continue;
+            };
+
+            let whole = caps.get(0).unwrap();
+
+            if start < whole.start() {
+                node.insert_before(alloc(NodeValue::Text(
+                    text[start..whole.start()].to_string().into(),
+                )));
+            }
+
+            let link = alloc(NodeValue::Link(
+                NodeLink {
+                    url: format!("/tags/{}", slug(&name)),
+                    title: String::new(),
+                }
+                .into(),
+            ));
+            link.append(alloc(NodeValue::Text(caps[1].trim().to_string().into())));
+            node.insert_before(link);
+
+            if !found.contains(&name) {
+                found.push(name);
+            }
+
+            start = whole.end();
+            replaced = true;
+        }
+
+        if !replaced {
+            continue;
+        }
+
+        if start < text.len() {
+            node.insert_before(alloc(NodeValue::Text(text[start..].to_string().into())));
+        }
+
+        node.detach();
+    }
+
+    found
+}
+
+/// Add the class for its tag's state to each link to a referenced tag.
+fn style(html: &str, found: &[String], states: &HashMap<String, TagState>) -> String {
+    let classes: HashMap<String, &str> = found
+        .iter()
+        .map(|name| {
+            let state = states.get(name).copied().unwrap_or(TagState::Exists);
+            (format!("/tags/{}", slug(name)), state.class())
+        })
+        .collect();
+
+    TAG_ANCHOR
+        .replace_all(html, |caps: &Captures| match classes.get(&caps[1]) {
+            Some(class) => format!(r#"{} class="{class}""#, &caps[0]),
+            None => caps[0].to_string(),
+        })
+        .into_owned()
+}
+
+/// Normalize a tag name as written, or `None` if it cannot be a tag.
+fn normalize(name: &str) -> Option<String> {
+    let name = name
+        .to_lowercase()
+        .replace(
+            [
+                '\u{00b4}', '\u{2018}', '\u{2019}', '\u{201a}', '\u{201b}', '\u{2032}',
+            ],
+            "'",
+        )
+        .replace(
+            ['\u{201c}', '\u{201d}', '\u{201e}', '\u{201f}', '\u{2033}'],
+            "\"",
+        )
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    if name.is_empty() || name.len() > MAX_NAME_BYTES {
+        None
+    } else {
+        Some(name)
+    }
+}
+
+/// Encode a tag name as a URL path segment, as `Philomena.Slug.slug/1`
+/// followed by percent-encoding.
+pub fn slug(name: &str) -> String {
+    let slug = name
+        .replace('-', "-dash-")
+        .replace('/', "-fwslash-")
+        .replace('\\', "-bwslash-")
+        .replace(':', "-colon-")
+        .replace('.', "-dot-")
+        .replace('+', "-plus-")
+        .replace(' ', "+");
+
+    let mut out = String::with_capacity(slug.len());
+
+    for byte in slug.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'~' | b'+' => {
+                out.push(byte as char)
+            }
+            _ => out.push_str(&format!("%{byte:02X}")),
+        }
+    }
+
+    out
+}
@@ -0,0 +1,190 @@
+// Plain-text rendering of Markdown documents.
+//
+// Used where HTML is not an option (email notifications, search indexing,
+// OpenGraph descriptions). The document is parsed with the same rules as
+// the HTML renderer and then flattened: blocks are separated by blank lines,
+// spoilers collapse to a placeholder so their contents never leak, links
+// become "text (url)", images their alt text (or URL when it is empty), and
+// table cells are joined with tabs. Greentext is ordinary text to the parser
+// and comes through unchanged.
+//
+// Flattening recurses once per level of the tree, so documents nested
+// deeper than `MAX_DEPTH` are instead reduced to their text in order, one
+// block per line, by a walk which needs no recursion.
+
+use crate::markdown;
+use crate::markdown_limits;
+use comrak::arena_tree::NodeEdge;
+use comrak::nodes::{AstNode, ListType, NodeValue};
+use comrak::{Arena, parse_document};
+
+/// Text emitted in place of spoilered content.
+pub const SPOILER_PLACEHOLDER: &str = "[spoiler]";
+
+/// Deepest nesting of nodes which is flattened block by block.
+const MAX_DEPTH: usize = 128;
+
+/// Render `input` as plain text.
+pub fn to_text(input: &str) -> String {
+    let mut options = markdown::common_options();
+    options.extension.replacements = Some(Default::default());
+
+    let arena = Arena::new();
+    let root = parse_document(&arena, input, &options);
+    let (depth, _) = markdown_limits::measure(root);
+
+    if depth > MAX_DEPTH {
+        return text_only(root);
+    }
+
+    blocks(root).join("\n\n")
+}
+
+/// The text of `root` in document order, with a line break after each
+/// block and spoilers still hidden.
+fn text_only<'a>(root: &'a AstNode<'a>) -> String {
+    let mut out = String::new();
+    let mut spoilers = 0;
+
+    for edge in root.traverse() {
+        match edge {
+            NodeEdge::Start(node) => match node.data.borrow().value {
+                NodeValue::SpoileredText => {
+                    if spoilers == 0 {
+                        out.push_str(SPOILER_PLACEHOLDER);
+                    }
+
+                    spoilers += 1;
+                }
+                _ if spoilers > 0 => {}
+                NodeValue::Text(ref text) => out.push_str(text),
+                NodeValue::Code(ref code) => out.push_str(&code.literal),
+                NodeValue::CodeBlock(ref code) => out.push_str(&code.literal),
+                NodeValue::SoftBreak | NodeValue::LineBreak => out.push('\n'),
+                _ => {}
+            },
+            NodeEdge::End(node) => {
+                let value = &node.data.borrow().value;
+
+                if matches!(value, NodeValue::SpoileredText) {
+                    spoilers -= 1;
+                } else if spoilers == 0 && value.block() && !out.ends_with('\n') {
+                    out.push('\n');
+                }
+            }
+        }
+    }
+
+    out.trim().to_string()
+}
+
+/// Render the block-level children of `node`, one string per block.
+fn blocks<'a>(node: &'a AstNode<'a>) -> Vec<String> {
+    node.children()
+        .map(block)
+        .filter(|text| !text.is_empty())
+        .collect()
+}
+
+fn block<'a>(node: &'a AstNode<'a>) -> String {
+    let value = node.data.borrow().value.clone();
+
+    match value {
+        NodeValue::Paragraph
+        | NodeValue::Heading(..)
+        | NodeValue::TableCell
+        | NodeValue::DescriptionTerm => inlines(node),
+        NodeValue::BlockQuote => prefix_lines(&blocks(node).join("\n\n"), "> ", ">"),
+        NodeValue::List(list) => {
+            let mut number = list.start;
+
+            node.children()
+                .map(|item| {
+                    let marker = match list.list_type {
+                        ListType::Bullet => "- ".to_string(),
+                        ListType::Ordered => format!("{number}. "),
+                    };
+                    number += 1;
+
+                    let text = blocks(item).join(if list.tight { "\n" } else { "\n\n" });
+                    let indent = " ".repeat(marker.len());
+
+                    format!("{marker}{}", prefix_lines(&text, &indent, "").trim_start())
+                })
+                .collect::<Vec<_>>()
+                .join(if list.tight { "\n" } else { "\n\n" })
+        }
+        NodeValue::DescriptionList | NodeValue::DescriptionItem(..) => blocks(node).join("\n"),
+        NodeValue::DescriptionDetails => prefix_lines(&blocks(node).join("\n\n"), "  ", ""),
+        NodeValue::CodeBlock(code) => code.literal.trim_end_matches('\n').to_string(),
+        NodeValue::HtmlBlock(html) => html.literal.trim_end_matches('\n').to_string(),
+        NodeValue::ThematicBreak => "----".into(),
+        NodeValue::Table(..) => node
+            .children()
+            .map(|row| row.children().map(block).collect::<Vec<_>>().join("\t"))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => blocks(node).join("\n\n"),
+    }
+}
+
+/// Render the inline children of `node` as a single string.
+pub(crate) fn inlines<'a>(node: &'a AstNode<'a>) -> String {
+    let mut out = String::new();
+
+    for child in node.children() {
+        inline(child, &mut out);
+    }
+
+    out.trim().to_string()
+}
+
+fn inline<'a>(node: &'a AstNode<'a>, out: &mut String) {
+    let value = node.data.borrow().value.clone();
+
+    match value {
+        NodeValue::Text(text) => out.push_str(&text),
+        NodeValue::Code(code) => out.push_str(&code.literal),
+        NodeValue::HtmlInline(html) => out.push_str(&html),
+        NodeValue::SoftBreak | NodeValue::LineBreak => out.push('\n'),
+        NodeValue::SpoileredText => out.push_str(SPOILER_PLACEHOLDER),
+        NodeValue::Link(link) => {
+            let text = inlines(node);
+
+            if text.is_empty() || text == link.url {
+                out.push_str(&link.url);
+            } else {
+                out.push_str(&format!("{text} ({})", link.url));
+            }
+        }
+        NodeValue::Image(link) => {
+            let alt = inlines(node);
+
+            if alt.is_empty() {
+                out.push_str(&link.url);
+            } else {
+                out.push_str(&alt);
+            }
+        }
+        _ => {
+            for child in node.children() {
+                inline(child, out);
+            }
+        }
+    }
+}
+
+/// Prefix every line of `text` with `prefix`, using `blank_prefix` for empty
+/// lines so that no trailing whitespace is produced.
+fn prefix_lines(text: &str, prefix: &str, blank_prefix: &str) -> String {
+    text.lines()
+        .map(|line| {
+            if line.is_empty() {
+                blank_prefix.to_string()
+            } else {
+                format!("{prefix}{line}")
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
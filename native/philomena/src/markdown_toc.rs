@@ -0,0 +1,85 @@
+// Heading anchors and tables of contents for trusted documents.
+//
+// Static and rules pages are long enough to want a sidebar. Rendering goes
+// through the unsafe (trusted) path with comrak's header IDs enabled, which
+// slugs each heading's text and de-duplicates repeats with a numeric suffix
+// (`rules`, `rules-1`, ...). The table of contents runs the same `Anchorizer`
+// over the same heading text, in document order, so it links to exactly the
+// IDs the page carries. Headings are then nested by level.
+
+use crate::{markdown, markdown_text};
+use comrak::nodes::NodeValue;
+use comrak::{Anchorizer, Arena, parse_document};
+use rustler::NifMap;
+use std::collections::HashMap;
+
+#[derive(NifMap, Clone, Debug, PartialEq, Eq)]
+pub struct TocEntry {
+    pub level: u8,
+    pub title: String,
+    pub anchor: String,
+    pub children: Vec<TocEntry>,
+}
+
+/// Render trusted `input` with heading anchors, returning the HTML and the
+/// nested table of contents.
+pub fn to_html_unsafe_with_toc(
+    input: &str,
+    reps: HashMap<String, String>,
+) -> (String, Vec<TocEntry>) {
+    let mut options = markdown::unsafe_options();
+    options.extension.replacements = Some(reps);
+    options.extension.header_id_prefix = Some(String::new());
+
+    let arena = Arena::new();
+    let root = parse_document(&arena, input, &options);
+    let mut anchorizer = Anchorizer::new();
+
+    let flat = root
+        .descendants()
+        .filter_map(|node| match node.data.borrow().value {
+            NodeValue::Heading(ref heading) => Some(TocEntry {
+                level: heading.level,
+                title: markdown_text::inlines(node),
+                anchor: anchorizer.anchorize(&node.collect_text()),
+                children: vec![],
+            }),
+            _ => None,
+        })
+        .collect();
+
+    let html = markdown::finish(markdown::format_html(root, &options));
+
+    (html, nest(flat))
+}
+
+/// Nest a flat list of headings so each entry holds the deeper headings
+/// which follow it.
+fn nest(flat: Vec<TocEntry>) -> Vec<TocEntry> {
+    let mut roots: Vec<TocEntry> = vec![];
+    let mut stack: Vec<TocEntry> = vec![];
+
+    for entry in flat {
+        while stack.last().is_some_and(|top| top.level >= entry.level) {
+            close(&mut stack, &mut roots);
+        }
+        stack.push(entry);
+    }
+
+    while !stack.is_empty() {
+        close(&mut stack, &mut roots);
+    }
+
+    roots
+}
+
+fn close(stack: &mut Vec<TocEntry>, roots: &mut Vec<TocEntry>) {
+    let Some(entry) = stack.pop() else {
+        return;
+    };
+
+    match stack.last_mut() {
+        Some(parent) => parent.children.push(entry),
+        None => roots.push(entry),
+    }
+}
@@ -0,0 +1,164 @@
+// `@username` mentions.
+//
+// Like `>>1234` image mentions, user mentions are resolved against a map
+// supplied by the caller, here from username to profile URL. Only names in
+// the map become links, so a stray `@` never produces a dead link and the
+// caller decides who can be mentioned. Because usernames may contain spaces,
+// the longest name matching the text after the `@` wins. Matching runs over
+// the parsed document's text nodes, so mentions inside code, links and
+// escapes are left alone, and the names that were linked are returned for
+// sending notifications.
+
+use crate::markdown;
+use comrak::nodes::{AstNode, NodeLink, NodeValue};
+use comrak::{Arena, parse_document};
+use std::collections::HashMap;
+
+/// Render user-input `input`, linking `@name` for each name in `users` to its
+/// URL. Returns the HTML and the names mentioned, in order of first mention.
+pub fn to_html_with_users(
+    input: &str,
+    reps: HashMap<String, String>,
+    users: HashMap<String, String>,
+) -> (String, Vec<String>) {
+    let mut options = markdown::common_options();
+    options.extension.replacements = Some(reps);
+
+    // Parse escapes into their own nodes so that `\@name` is not a mention;
+    // they render as plain text again without the option.
+    let mut parse_options = options.clone();
+    parse_options.render.escaped_char_spans = true;
+
+    let arena = Arena::new();
+    let root = parse_document(&arena, input, &parse_options);
+    let mentioned = link_users(root, &users, |value| arena.alloc(value.into()));
+
+    (
+        markdown::finish(markdown::format_html(root, &options)),
+        mentioned,
+    )
+}
+
+enum Piece<'t, 'u> {
+    Text(&'t str),
+    User(&'t str, &'u str),
+}
+
+/// Replace each mention of a user in `root` with a link, allocating new
+/// nodes with `alloc`. Returns the names mentioned.
+fn link_users<'a, F>(
+    root: &'a AstNode<'a>,
+    users: &HashMap<String, String>,
+    alloc: F,
+) -> Vec<String>
+where
+    F: Fn(NodeValue) -> &'a AstNode<'a>,
+{
+    let mut names: Vec<&String> = users.keys().filter(|name| !name.is_empty()).collect();
+    names.sort_by_key(|name| std::cmp::Reverse(name.len()));
+
+    let mut mentioned: Vec<String> = vec![];
+    let nodes: Vec<_> = root.descendants().filter(|node| linkable(node)).collect();
+
+    for node in nodes {
+        let text = match node.data.borrow().value {
+            NodeValue::Text(ref text) => text.to_string(),
+            _ => continue,
+        };
+
+        let pieces = split(&text, &names);
+
+        if !pieces.iter().any(|piece| matches!(piece, Piece::User(..))) {
+            continue;
+        }
+
+        for piece in pieces {
+            let new = match piece {
+                Piece::Text(text) => alloc(NodeValue::Text(text.to_string().into())),
+                Piece::User(text, name) => {
+                    let link = alloc(NodeValue::Link(
+                        NodeLink {
+                            url: users[name].clone(),
+                            title: String::new(),
+                        }
+                        .into(),
+                    ));
+                    link.append(alloc(NodeValue::Text(text.to_string().into())));
+
+                    if !mentioned.iter().any(|m| m == name) {
+                        mentioned.push(name.to_string());
+                    }
+
+                    link
+                }
+            };
+
+            node.insert_before(new);
+        }
+
+        node.detach();
+    }
+
+    mentioned
+}
+
+/// Whether `node` is text outside of any link, image or escape.
+pub(crate) fn linkable<'a>(node: &'a AstNode<'a>) -> bool {
+    matches!(node.data.borrow().value, NodeValue::Text(..))
+        && !node.ancestors().skip(1).any(|a| {
+            matches!(
+                a.data.borrow().value,
+                NodeValue::Link(..) | NodeValue::Image(..) | NodeValue::Escaped
+            )
+        })
+}
+
+/// Split `text` into plain text and mentions of `names`, which are sorted
+/// longest first.
+fn split<'t, 'u>(text: &'t str, names: &[&'u String]) -> Vec<Piece<'t, 'u>> {
+    let mut pieces = vec![];
+    let mut start = 0;
+    let mut i = 0;
+
+    while let Some(offset) = text[i..].find('@') {
+        let at = i + offset;
+        i = at + 1;
+
+        let after_word = text[..at]
+            .chars()
+            .next_back()
+            .is_some_and(|c| c.is_alphanumeric() || c == '_');
+
+        if after_word {
+            continue;
+        }
+
+        let rest = &text[at + 1..];
+        let Some(&name) = names.iter().find(|name| {
+            rest.get(..name.len())
+                .is_some_and(|prefix| prefix.eq_ignore_ascii_case(name))
+                && rest[name.len()..]
+                    .chars()
+                    .next()
+                    .is_none_or(|c| !(c.is_alphanumeric() || c == '_'))
+        }) else {
+            continue;
+        };
+
+        let end = at + 1 + name.len();
+
+        if start < at {
+            pieces.push(Piece::Text(&text[start..at]));
+        }
+        pieces.push(Piece::User(&text[at..end], name.as_str()));
+
+        start = end;
+        i = end;
+    }
+
+    if start < text.len() {
+        pieces.push(Piece::Text(&text[start..]));
+    }
+
+    pieces
+}
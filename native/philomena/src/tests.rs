@@ -25,8 +25,10 @@ where
 }
 
 fn html_opts_w(input: &str, expected: &str, options: &comrak::Options) {
-    let output = comrak::markdown_to_html(input, options);
+    assert_output(input, expected, &comrak::markdown_to_html(input, options));
+}
 
+fn assert_output(input: &str, expected: &str, output: &str) {
     if output != expected {
         println!("Input:");
         println!("========================");
@@ -273,8 +275,1116 @@ fn auto_relative_links() {
     );
 }
 
+#[test]
+fn ast_reports_kinds_attrs_and_positions() {
+    use crate::markdown_ast::{AttrValue, Node, parse};
+
+    let root = parse("# Title\n\n[link](https://example.com) ||spoiler||").unwrap();
+
+    assert_eq!(root.kind, "document");
+    assert_eq!(root.children.len(), 2);
+
+    let heading = &root.children[0];
+    assert_eq!(heading.kind, "heading");
+    assert_eq!(heading.attrs, vec![("level", AttrValue::Int(1))]);
+    assert_eq!(heading.sourcepos, ((1, 1), (1, 7)));
+
+    let paragraph = &root.children[1];
+    let kinds: Vec<&str> = paragraph.children.iter().map(|n: &Node| n.kind).collect();
+    assert_eq!(kinds, vec!["link", "text", "spoiler"]);
+    assert_eq!(
+        paragraph.children[0].attrs,
+        vec![
+            ("url", AttrValue::Str("https://example.com".into())),
+            ("title", AttrValue::Str("".into())),
+        ]
+    );
+    assert_eq!(paragraph.children[2].children[0].kind, "text");
+}
+
+#[test]
+fn ast_keeps_code_literals() {
+    let root = crate::markdown_ast::parse("```rust\nfn main() {}\n```\n").unwrap();
+    let block = &root.children[0];
+
+    assert_eq!(block.kind, "code_block");
+    assert!(block.attrs.contains(&(
+        "literal",
+        crate::markdown_ast::AttrValue::Str("fn main() {}\n".into())
+    )));
+}
+
+#[test]
+fn ast_refuses_deep_nesting() {
+    assert_eq!(
+        crate::markdown_ast::parse(&"> ".repeat(5000)),
+        Err(crate::markdown_limits::LimitError::NestingTooDeep)
+    );
+    assert!(crate::markdown_ast::parse(&"> ".repeat(100)).is_ok());
+}
+
+#[test]
+fn mentions_are_extracted_with_suffixes() {
+    use crate::markdown_extract::{Mention, mentions};
+
+    assert_eq!(
+        mentions("hello >>1234p and >>5\n>>77s"),
+        vec![
+            Mention {
+                id: 1234,
+                suffix: "p".into(),
+                position: (1, 7)
+            },
+            Mention {
+                id: 5,
+                suffix: "".into(),
+                position: (1, 19)
+            },
+            Mention {
+                id: 77,
+                suffix: "s".into(),
+                position: (2, 1)
+            },
+        ]
+    );
+}
+
+#[test]
+fn mentions_skip_code_and_escapes() {
+    let found =
+        crate::markdown_extract::mentions("`>>1` >>2t\n\n```\n>>3p\n```\n\n\\>>4 >>99999999999");
+    let keys: Vec<String> = found.iter().map(|m| m.key()).collect();
+
+    assert_eq!(keys, vec!["2t"]);
+}
+
+#[test]
+fn mention_positions_follow_the_source() {
+    let found = crate::markdown_extract::mentions("don't -- &amp; look >>1234p\n\"a\" >>5");
+    let positions: Vec<_> = found.iter().map(|m| m.position).collect();
+
+    assert_eq!(positions, vec![(1, 21), (2, 5)]);
+}
+
+#[test]
+fn mention_positions_are_found_in_one_pass() {
+    let found = crate::markdown_extract::mentions(&"a >>1 ".repeat(20_000));
+
+    assert_eq!(found.len(), 20_000);
+    assert_eq!(found[1].position, (1, 9));
+    assert_eq!(found[19_999].position, (1, 6 * 19_999 + 3));
+}
+
+fn text(input: &str, expected: &str) {
+    assert_output(input, expected, &crate::markdown_text::to_text(input));
+}
+
+#[test]
+fn text_hides_spoilers_and_expands_links() {
+    text(
+        "The ||dog dies||, see [here](https://example.com) or https://example.org",
+        "The [spoiler], see here (https://example.com) or https://example.org",
+    );
+}
+
+#[test]
+fn text_uses_image_alt_or_url() {
+    text(
+        "![a pony](https://example.com/a.png) ![](https://example.com/b.png)",
+        "a pony https://example.com/b.png",
+    );
+}
+
+#[test]
+fn text_keeps_greentext_and_quotes_blocks() {
+    text(
+        ">implying\n\n> quoted\n\n# Heading\n\n- one\n- two",
+        ">implying\n\n> quoted\n\nHeading\n\n- one\n- two",
+    );
+}
+
+#[test]
+fn text_separates_table_cells_with_tabs() {
+    text("a | b\n--- | ---\n`c` | **d**\n", "a\tb\nc\td");
+}
+
+#[test]
+fn text_flattens_deep_nesting_without_recursing() {
+    text(
+        &format!("{}deep ||secret||\n\nafter", "> ".repeat(5000)),
+        "deep [spoiler]\nafter",
+    );
+}
+
+fn excerpt(input: &str, excerpt: crate::markdown_excerpt::ExcerptOptions, expected: &str) {
+    let (output, _) = crate::markdown_excerpt::truncate_html(
+        &comrak::markdown_to_html(input, &test_options()),
+        excerpt,
+    );
+
+    assert_output(input, expected, &output);
+}
+
+#[test]
+fn excerpt_closes_open_tags_inside_spoilers() {
+    excerpt(
+        "Intro ||secret **bold** text|| outro",
+        crate::markdown_excerpt::ExcerptOptions {
+            max_chars: 16,
+            ..Default::default()
+        },
+        "<div class=\"paragraph\">Intro <span class=\"spoiler\">secret <strong>bol\u{2026}</strong></span></div>",
+    );
+}
+
+#[test]
+fn excerpt_never_splits_entities() {
+    excerpt(
+        "a &amp; b",
+        crate::markdown_excerpt::ExcerptOptions {
+            max_chars: 3,
+            ..Default::default()
+        },
+        "<div class=\"paragraph\">a &amp;\u{2026}</div>",
+    );
+}
+
+#[test]
+fn excerpt_can_drop_spoilers() {
+    excerpt(
+        "Before ||hidden|| after",
+        crate::markdown_excerpt::ExcerptOptions {
+            drop_spoilers: true,
+            ..Default::default()
+        },
+        "<div class=\"paragraph\">Before  after</div>\n",
+    );
+}
+
+#[test]
+fn excerpt_keeps_embeds_whole_and_skips_hidden_text() {
+    let embed = concat!(
+        "<div class=\"image-show-container\">",
+        "<div class=\"block image-filtered hidden\">This image is blocked by your current filter</div>",
+        "<div class=\"image-show\"><img src=\"/thumb.png\"></div>",
+        "</div>",
+    );
+    let reps = HashMap::from([("1p".to_string(), embed.to_string())]);
+    let input = ">>1p more text";
+    let (output, truncated) = crate::markdown_excerpt::to_html_excerpt(
+        input,
+        reps,
+        crate::markdown_excerpt::ExcerptOptions {
+            max_chars: 3,
+            ..Default::default()
+        },
+    );
+
+    assert!(truncated);
+    assert_output(
+        input,
+        &format!("<div class=\"paragraph\">{embed} m\u{2026}</div>"),
+        &output,
+    );
+}
+
+#[test]
+fn excerpt_limits_blocks() {
+    excerpt(
+        "one\n\ntwo\n\nthree",
+        crate::markdown_excerpt::ExcerptOptions {
+            max_blocks: 2,
+            ..Default::default()
+        },
+        "<div class=\"paragraph\">one</div>\n<div class=\"paragraph\">two</div>",
+    );
+}
+
+#[test]
+fn lint_reports_unclosed_spoilers() {
+    use crate::markdown_lint::{WarningKind, lint};
+
+    let warnings = lint("||closed||\nnot ||closed\n`||code||`", None);
+
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].kind, WarningKind::UnclosedSpoiler);
+    assert_eq!((warnings[0].start, warnings[0].end), ((2, 5), (2, 6)));
+}
+
+#[test]
+fn lint_positions_follow_the_source() {
+    let warnings = crate::markdown_lint::lint("\"quoted\" ||open", None);
+
+    assert_eq!(warnings.len(), 1);
+    assert_eq!((warnings[0].start, warnings[0].end), ((1, 10), (1, 11)));
+}
+
+#[test]
+fn lint_finds_unclosed_spoilers_in_one_pass() {
+    let warnings = crate::markdown_lint::lint(&"a|| ".repeat(20_000), None);
+
+    assert_eq!(warnings.len(), 20_000);
+    assert_eq!(warnings[19_999].start, (1, 4 * 19_999 + 2));
+}
+
+#[test]
+fn lint_reports_empty_links_and_broken_tables() {
+    use crate::markdown_lint::{WarningKind, lint};
+
+    let warnings = lint("[  ](https://example.com)\n\na | b | c\n--- | ---\n", None);
+    let kinds: Vec<WarningKind> = warnings.iter().map(|w| w.kind).collect();
+
+    assert_eq!(
+        kinds,
+        vec![WarningKind::EmptyLink, WarningKind::BrokenTable]
+    );
+    assert_eq!((warnings[1].start, warnings[1].end), ((4, 1), (4, 9)));
+}
+
+#[test]
+fn lint_reports_missing_images_only_when_asked() {
+    use crate::markdown_lint::{WarningKind, lint};
+
+    assert!(lint(">>1 >>2p", None).is_empty());
+
+    let warnings = lint(">>1 >>2p", Some(vec![1]));
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].kind, WarningKind::BrokenMention);
+    assert_eq!((warnings[0].start, warnings[0].end), ((1, 5), (1, 8)));
+}
+
+fn profile_html(input: &str, profile: Profile, expected: &str) {
+    let mut options = profile_options(profile);
+    options.extension.image_url_rewriter = None;
+    options.extension.link_url_rewriter = None;
+    options.extension.replacements = Some(Default::default());
+
+    let arena = comrak::Arena::new();
+    let root = comrak::parse_document(&arena, input, &options);
+    restrict(root, profile);
+
+    assert_output(input, expected, &format_html(root, &options));
+}
+
+#[test]
+fn comment_profile_matches_common_options() {
+    profile_html(
+        "# Heading\n\n![alt](https://example.com/a.png)",
+        Profile::Comment,
+        concat!(
+            "<h1>Heading</h1>\n",
+            "<div class=\"paragraph\"><span class=\"imgspoiler\"><img src=\"https://example.com/a.png\" alt=\"alt\" /></span></div>\n"
+        ),
+    );
+}
+
+#[test]
+fn signature_profile_drops_images_headings_and_tables() {
+    profile_html(
+        "# Heading\n\n![alt](https://example.com/a.png)\n\na | b\n--- | ---\nc | d\n",
+        Profile::Signature,
+        concat!(
+            "<div class=\"paragraph\">Heading</div>\n",
+            "<div class=\"paragraph\">alt</div>\n",
+            "<div class=\"paragraph\">a | b<br />\n\u{2014} | \u{2014}<br />\nc | d</div>\n"
+        ),
+    );
+}
+
+#[test]
+fn user_description_profile_only_drops_headings() {
+    profile_html(
+        "## About me\n\n||secret||",
+        Profile::UserDescription,
+        "<div class=\"paragraph\">About me</div>\n<div class=\"paragraph\"><span class=\"spoiler\">secret</span></div>\n",
+    );
+}
+
+#[test]
+fn signature_profile_ignores_image_mentions() {
+    let reps = HashMap::from([("1p".to_string(), "<div class=\"embed\"></div>".to_string())]);
+    let options = HtmlOptions {
+        profile: Profile::Signature,
+        highlight: vec![],
+        sourcepos: false,
+    };
+
+    assert_output(
+        ">>1p",
+        "<div class=\"paragraph\">&gt;&gt;1p</div>\n",
+        &to_html_with(">>1p", reps, &options),
+    );
+}
+
+#[test]
+fn static_page_profile_still_escapes_html() {
+    let options = HtmlOptions {
+        profile: Profile::StaticPage,
+        highlight: vec![],
+        sourcepos: false,
+    };
+
+    assert_output(
+        "<b>hi</b>",
+        "<div class=\"paragraph\">&lt;b&gt;hi&lt;/b&gt;</div>\n",
+        &to_html_with("<b>hi</b>", HashMap::new(), &options),
+    );
+}
+
+#[test]
+fn links_are_extracted_with_kinds_and_rewrites() {
+    use crate::markdown_extract::{Link, LinkKind, links_with_options};
+
+    let mut options = crate::markdown_extract::extract_options();
+    options.extension.image_url_rewriter = Some(Arc::new(|url: &str| format!("proxy:{url}")));
+    options.extension.link_url_rewriter = None;
+
+    let found = links_with_options(
+        "[text][ref] ![img](https://example.com/a.png)\nhttps://example.org `https://example.net`\n\n[ref]: https://example.com/ref",
+        &options,
+    );
+
+    assert_eq!(
+        found,
+        vec![
+            Link {
+                kind: LinkKind::Link,
+                url: "https://example.com/ref".into(),
+                rewritten: "https://example.com/ref".into(),
+                position: (1, 1),
+            },
+            Link {
+                kind: LinkKind::Image,
+                url: "https://example.com/a.png".into(),
+                rewritten: "proxy:https://example.com/a.png".into(),
+                position: (1, 13),
+            },
+            Link {
+                kind: LinkKind::Autolink,
+                url: "https://example.org".into(),
+                rewritten: "https://example.org".into(),
+                position: (2, 1),
+            },
+        ]
+    );
+}
+
+#[test]
+fn explicit_links_to_their_own_url_are_not_autolinks() {
+    use crate::markdown_extract::{LinkKind, links};
+
+    let kinds: Vec<_> = links("[https://x.org](https://x.org) https://y.org")
+        .into_iter()
+        .map(|link| (link.kind, link.position))
+        .collect();
+
+    assert_eq!(
+        kinds,
+        vec![(LinkKind::Link, (1, 1)), (LinkKind::Autolink, (1, 32))]
+    );
+}
+
+#[test]
+fn external_links_get_rel_and_target() {
+    use crate::links::{LinkPolicy, apply};
+
+    let domains = vec!["example.com".to_string()].into_iter().collect();
+    let policy = LinkPolicy {
+        new_tab: true,
+        blocklist: vec![],
+    };
+
+    assert_eq!(
+        apply(
+            "<a href=\"/images/1\">a</a> <a href=\"https://example.com/x\">b</a> <a href=\"https://other.org/?a=1&amp;b=2\" title=\"t\">c</a>",
+            Some(&domains),
+            &policy,
+        ),
+        "<a href=\"/images/1\">a</a> <a href=\"https://example.com/x\">b</a> <a href=\"https://other.org/?a=1&amp;b=2\" title=\"t\" rel=\"nofollow ugc noopener\" target=\"_blank\">c</a>",
+    );
+}
+
+#[test]
+fn blocklisted_links_are_removed() {
+    use crate::links::{LinkPolicy, apply};
+
+    let policy = LinkPolicy {
+        new_tab: false,
+        blocklist: vec!["evil.com".into(), "*.malware.net".into()],
+    };
+
+    assert_eq!(
+        apply(
+            "<a href=\"https://evil.com/x\">one</a> <a href=\"https://cdn.malware.net/\"><em>two</em></a> <a href=\"https://notevil.com/\">three</a> <a href=\"https://malware.net/\">four</a>",
+            None,
+            &policy,
+        ),
+        concat!(
+            "<span class=\"link--blocked\" title=\"Link removed\">one</span> ",
+            "<span class=\"link--blocked\" title=\"Link removed\"><em>two</em></span> ",
+            "<a href=\"https://notevil.com/\" rel=\"nofollow ugc noopener\">three</a> ",
+            "<a href=\"https://malware.net/\" rel=\"nofollow ugc noopener\">four</a>"
+        ),
+    );
+}
+
+#[test]
+fn protocol_relative_links_are_off_site() {
+    use crate::links::{LinkPolicy, apply};
+
+    let policy = LinkPolicy {
+        new_tab: false,
+        blocklist: vec!["evil.com".into()],
+    };
+
+    assert_eq!(
+        apply(
+            "<a href=\"//evil.com/x\">a</a> <a href=\"/\\evil.com/\">b</a> <a href=\"//other.org/\">c</a> <a href=\"/images/1\">d</a>",
+            None,
+            &policy,
+        ),
+        concat!(
+            "<span class=\"link--blocked\" title=\"Link removed\">a</span> ",
+            "<span class=\"link--blocked\" title=\"Link removed\">b</span> ",
+            "<a href=\"//other.org/\" rel=\"nofollow ugc noopener\">c</a> ",
+            "<a href=\"/images/1\">d</a>"
+        ),
+    );
+}
+
+#[test]
+fn link_titles_cannot_stand_in_for_rel_or_target() {
+    use crate::links::{LinkPolicy, apply};
+
+    let policy = LinkPolicy {
+        new_tab: true,
+        blocklist: vec![],
+    };
+
+    assert_eq!(
+        apply(
+            "<a href=\"http://a.com\" title=\" rel=x target=y\">y</a>",
+            None,
+            &policy,
+        ),
+        "<a href=\"http://a.com\" title=\" rel=x target=y\" rel=\"nofollow ugc noopener\" target=\"_blank\">y</a>",
+    );
+}
+
+#[test]
+fn toc_nests_headings_and_deduplicates_anchors() {
+    use crate::markdown_toc::{TocEntry, to_html_unsafe_with_toc};
+
+    let (html, toc) = to_html_unsafe_with_toc(
+        "# Rules\n\n## General\n\n## *Images* `here`\n\n# Rules\n",
+        HashMap::new(),
+    );
+
+    let entry = |level, title: &str, anchor: &str, children| TocEntry {
+        level,
+        title: title.into(),
+        anchor: anchor.into(),
+        children,
+    };
+
+    assert_eq!(
+        toc,
+        vec![
+            entry(
+                1,
+                "Rules",
+                "rules",
+                vec![
+                    entry(2, "General", "general", vec![]),
+                    entry(2, "Images here", "images-here", vec![]),
+                ]
+            ),
+            entry(1, "Rules", "rules-1", vec![]),
+        ]
+    );
+    assert!(html.contains("<h2 id=\"images-here\">"));
+    assert!(html.contains("<h1 id=\"rules-1\">"));
+}
+
+fn highlighted(input: &str, languages: &[&str]) -> String {
+    use crate::markdown_highlight::Highlighter;
+
+    let options = test_options();
+    let highlighter = Highlighter::new(languages.iter().map(|l| l.to_string()).collect());
+    let mut plugins = comrak::options::Plugins::default();
+    plugins.render.codefence_syntax_highlighter = Some(&highlighter);
+
+    comrak::markdown_to_html_with_plugins(input, &options, &plugins)
+}
+
+#[test]
+fn highlighting_uses_classes_for_allowed_languages() {
+    let output = highlighted("```rust\nfn main() {}\n```\n", &["rust"]);
+
+    assert!(output.starts_with("<pre lang=\"rust\"><code>"));
+    assert!(output.contains("<span class=\"hl-"));
+    assert!(!output.contains("style="));
+}
+
+#[test]
+fn highlighting_falls_back_to_escaped_code() {
+    let plain = "<pre lang=\"rust\"><code>fn a() -&gt; &amp;str {}\n</code></pre>\n";
+
+    assert_eq!(highlighted("```rust\nfn a() -> &str {}\n```\n", &[]), plain);
+
+    let huge = format!(
+        "```rust\n{}\n```\n",
+        "x".repeat(crate::markdown_highlight::MAX_HIGHLIGHT_BYTES + 1)
+    );
+    assert!(!highlighted(&huge, &["rust"]).contains("<span"));
+}
+
+#[test]
+fn highlighting_budget_covers_the_whole_render() {
+    let half = format!(
+        "```rust\n{}\n```\n\n",
+        "x".repeat(crate::markdown_highlight::MAX_HIGHLIGHT_BYTES / 2)
+    );
+    let output = highlighted(&half.repeat(2), &["rust"]);
+
+    assert_eq!(output.matches("<pre").count(), 2);
+    assert_eq!(output.matches("<span class=\"hl-source").count(), 1);
+}
+
+#[test]
+fn sourcepos_is_kept_on_blocks_only() {
+    let mut options = test_options();
+    options.render.sourcepos = true;
+
+    let output = strip_inline_sourcepos(&comrak::markdown_to_html(
+        "> *a* [b](https://example.com)\n> c",
+        &options,
+    ));
+
+    assert!(output.contains("<blockquote data-sourcepos=\"1:1-2:3\">"));
+    assert!(output.contains("<em>a</em> <a href=\"https://example.com\">b</a>"));
+}
+
+#[test]
+fn quote_keeps_greentext_and_unembeds_mentions() {
+    assert_eq!(
+        crate::markdown_quote::quote(
+            ">implying\r\nlook >>1234p `>>5p`\r\n\r\n\r\nbye\r\n",
+            "Some_User",
+            "/images/1#comment_2",
+            2
+        ),
+        concat!(
+            "[Some\\_User](/images/1#comment_2)\n",
+            "> >implying\n",
+            "> look >>1234 `>>5p`\n",
+            ">\n",
+            "> bye\n",
+            "\n"
+        ),
+    );
+}
+
+#[test]
+fn quote_unembeds_mentions_after_smart_punctuation() {
+    assert_eq!(
+        crate::markdown_quote::quote("it's >>1p -- 'see' >>2s", "a", "/b", 2),
+        "[a](/b)\n> it's >>1 -- 'see' >>2\n\n",
+    );
+}
+
+#[test]
+fn quote_strips_quotes_beyond_max_depth() {
+    assert_eq!(
+        crate::markdown_quote::quote("> > deep\n> shallow\n\nown", "a", "/b", 2),
+        "[a](/b)\n> > shallow\n>\n> own\n\n",
+    );
+    assert_eq!(
+        crate::markdown_quote::quote("> shallow\n\nown", "a", "/b", 1),
+        "[a](/b)\n> own\n\n",
+    );
+}
+
+#[test]
+fn textile_converts_inline_markup() {
+    let conversion = crate::textile::to_markdown(concat!(
+        "*bold* _it_ +u+ -s- @co`de@ ??c?? [spoiler]sp *b*[/spoiler]\r\n",
+        "\"a *b*\":https://x.com/a_b. !/img/1.png!:https://x.com\r\n",
+        "[==*raw*==] ==_lit_== x_y_z a|b"
+    ));
+
+    assert_eq!(
+        conversion.markdown,
+        concat!(
+            "**bold** *it* __u__ ~~s~~ ``co`de`` *c* ||sp **b**||\n",
+            "[a **b**](https://x.com/a_b). [![](/img/1.png)](https://x.com)\n",
+            "\\*raw\\* \\_lit\\_ x\\_y\\_z a\\|b"
+        ),
+    );
+    assert!(conversion.unconverted.is_empty());
+}
+
+#[test]
+fn textile_escapes_block_markers() {
+    assert_eq!(
+        crate::textile::to_markdown("# not heading\n1. not list\n- not item").markdown,
+        "\\# not heading\n1\\. not list\n\\- not item",
+    );
+}
+
+#[test]
+fn textile_escapes_quote_markers_but_not_mentions() {
+    assert_eq!(
+        crate::textile::to_markdown("> not quoted\n>not green\n>>1234 embedded").markdown,
+        "\\> not quoted\n\\>not green\n>>1234 embedded",
+    );
+}
+
+#[test]
+fn textile_scans_unclosed_spans_once() {
+    let conversion = crate::textile::to_markdown(&"*a ".repeat(40_000));
+
+    assert_eq!(conversion.markdown, "\\*a ".repeat(40_000).trim_end());
+}
+
+#[test]
+fn textile_converts_quotes() {
+    let conversion = crate::textile::to_markdown(
+        "intro\n[bq=\"Some\"]quoted\n[bq]deeper[/bq]\nback[/bq]\nafter",
+    );
+
+    assert_eq!(
+        conversion.markdown,
+        "intro\n\n> quoted\n>\n> > deeper\n>\n> back\n\nafter",
+    );
+    assert!(conversion.unconverted.is_empty());
+}
+
+#[test]
+fn textile_reports_unbalanced_tags() {
+    let conversion = crate::textile::to_markdown("a [/bq]\nb [spoiler]open\n[bq]rest");
+
+    assert_eq!(
+        conversion.markdown,
+        "a \\[/bq\\]\nb \\[spoiler\\]open\n\n> rest"
+    );
+    assert_eq!(
+        conversion
+            .unconverted
+            .iter()
+            .map(|u| (u.line, u.text.as_str()))
+            .collect::<Vec<_>>(),
+        vec![(1, "[/bq]"), (2, "[spoiler]"), (3, "[bq]")],
+    );
+}
+
+#[test]
+fn textile_keeps_deeply_nested_markup_literal() {
+    let conversion = crate::textile::to_markdown(&format!(
+        "{}x{}",
+        "[spoiler]".repeat(40),
+        "[/spoiler]".repeat(40)
+    ));
+
+    assert_eq!(
+        conversion.markdown,
+        format!(
+            "{}{}x{}{}",
+            "||".repeat(33),
+            "\\[spoiler\\]".repeat(7),
+            "\\[/spoiler\\]".repeat(7),
+            "||".repeat(33)
+        )
+    );
+    assert_eq!(
+        conversion
+            .unconverted
+            .iter()
+            .map(|u| (u.line, u.reason.as_str()))
+            .collect::<Vec<_>>(),
+        vec![(1, "markup is nested too deeply to convert")],
+    );
+}
+
+#[test]
+fn batch_rendering_keeps_input_order() {
+    let inputs: Vec<String> = (0..50).map(|n| format!("*{n}* >>1p")).collect();
+    let reps = HashMap::from([("1p".to_string(), "<img>".to_string())]);
+
+    let shared = crate::markdown_batch::to_html_batch_shared(inputs.clone(), reps.clone());
+    let paired = crate::markdown_batch::to_html_batch(
+        inputs
+            .iter()
+            .map(|input| (input.clone(), reps.clone()))
+            .collect(),
+    );
+
+    for (n, input) in inputs.iter().enumerate() {
+        let expected = to_html(input, reps.clone());
+
+        assert_eq!(shared[n], expected);
+        assert_eq!(paired[n], expected);
+    }
+    assert!(crate::markdown_batch::to_html_batch(vec![]).is_empty());
+}
+
+#[test]
+fn batch_rendering_escapes_documents_which_fail() {
+    let inputs: Vec<String> = ["fine", "<b>\"bad\"</b> & worse", "also fine"]
+        .map(String::from)
+        .to_vec();
+
+    let rendered = crate::markdown_batch::render_all(inputs, |input: &String| {
+        assert!(!input.contains("bad"), "cannot render");
+        input.to_uppercase()
+    });
+
+    assert_eq!(
+        rendered,
+        vec![
+            "FINE".to_string(),
+            "<div class=\"paragraph\">&lt;b&gt;&quot;bad&quot;&lt;/b&gt; &amp; worse</div>\n"
+                .to_string(),
+            "ALSO FINE".to_string(),
+        ]
+    );
+}
+
+#[test]
+fn limited_rendering_enforces_limits() {
+    use crate::markdown_limits::{LimitError, Limits, to_html_limited};
+
+    let reps = HashMap::from([("1p".to_string(), "x".repeat(1000))]);
+    let limits = Limits {
+        max_input_bytes: 64,
+        max_output_bytes: 512,
+        max_depth: 4,
+        max_table_cells: 4,
+    };
+
+    assert_eq!(
+        to_html_limited("*hello*", reps.clone(), &limits),
+        Ok(to_html("*hello*", reps.clone()))
+    );
+    assert_eq!(
+        to_html_limited(&"a".repeat(65), reps.clone(), &limits),
+        Err(LimitError::InputTooLarge)
+    );
+    assert_eq!(
+        to_html_limited(">>1p", reps.clone(), &limits),
+        Err(LimitError::OutputTooLarge)
+    );
+    assert_eq!(
+        to_html_limited("> > > a", reps.clone(), &limits),
+        Err(LimitError::NestingTooDeep)
+    );
+    assert_eq!(
+        to_html_limited("|a|b|c|\n|-|-|-|\n|d|e|f|", reps.clone(), &limits),
+        Err(LimitError::TooManyTableCells)
+    );
+    assert!(to_html_limited("> > > a", reps, &Limits::default()).is_ok());
+}
+
+#[test]
+fn limited_rendering_counts_link_attributes() {
+    use crate::markdown_limits::{LimitError, Limits, to_html_limited};
+
+    let input = "https://example.com";
+    let html = to_html_limited(input, HashMap::new(), &Limits::default()).unwrap();
+    let limits = |max_output_bytes| Limits {
+        max_output_bytes,
+        ..Limits::default()
+    };
+
+    assert!(html.contains(" rel=\"nofollow ugc noopener\""));
+    assert_eq!(
+        to_html_limited(input, HashMap::new(), &limits(html.len())),
+        Ok(html.clone())
+    );
+    assert_eq!(
+        to_html_limited(input, HashMap::new(), &limits(html.len() - 1)),
+        Err(LimitError::OutputTooLarge)
+    );
+}
+
+#[test]
+fn format_normalizes_source() {
+    assert_eq!(
+        crate::markdown_format::format("* a\r\n* b   \r\n\r\n\r\ntext  "),
+        "- a\n- b\n\ntext\n"
+    );
+    assert_eq!(crate::markdown_format::format(""), "");
+}
+
+#[test]
+fn format_keeps_ascii_punctuation() {
+    assert_eq!(
+        crate::markdown_format::format("* He said \"don't\" -- ok..."),
+        "- He said \"don't\" -- ok...\n"
+    );
+}
+
+#[test]
+fn format_preserves_rendering() {
+    let options = test_options();
+
+    for input in [
+        ">implying\n>>1234p\n\n||spoiler|| __under__ ~sub~ ^sup^",
+        "| a | b |\n|---|:-:|\n| c | d |",
+        "1. one\n2. two\n\n    code\n\n> quote *em* **strong**",
+        "not *a list\n\\# not heading `co*de`",
+    ] {
+        let formatted = crate::markdown_format::format(input);
+
+        assert_eq!(
+            comrak::markdown_to_html(&formatted, &options),
+            comrak::markdown_to_html(input, &options),
+        );
+        assert_eq!(crate::markdown_format::format(&formatted), formatted);
+    }
+}
+
+#[test]
+fn user_mentions_link_known_names() {
+    let users = HashMap::from([
+        ("Some User".to_string(), "/profiles/some-user".to_string()),
+        ("bob".to_string(), "/profiles/bob".to_string()),
+    ]);
+
+    let (html, mentioned) = crate::markdown_users::to_html_with_users(
+        "hi @some user and @bobby, @bob! mail a@bob `@bob` \\@bob @Bob",
+        HashMap::new(),
+        users,
+    );
+
+    assert_eq!(
+        html,
+        concat!(
+            "<div class=\"paragraph\">hi <a href=\"/profiles/some-user\">@some user</a> and @bobby, ",
+            "<a href=\"/profiles/bob\">@bob</a>! mail a@bob <code>@bob</code> ",
+            "@bob <a href=\"/profiles/bob\">@Bob</a></div>\n"
+        )
+    );
+    assert_eq!(mentioned, vec!["Some User", "bob"]);
+}
+
+#[test]
+fn tag_links_use_tag_slugs() {
+    assert_eq!(
+        crate::markdown_tags::slug("artist:foo-bar"),
+        "artist-colon-foo-dash-bar"
+    );
+    assert_eq!(
+        crate::markdown_tags::slug("oc:a b/c.d+e"),
+        "oc-colon-a+b-fwslash-c-dot-d-plus-e"
+    );
+    assert_eq!(crate::markdown_tags::slug("Ünï"), "%C3%9Cn%C3%AF");
+
+    assert_eq!(
+        crate::markdown_tags::tags("{{Safe}} `{{code}}` \\{{escaped}} {{safe}} {{ two   words }}"),
+        vec!["safe", "two words"]
+    );
+}
+
+#[test]
+fn tag_links_render_with_states() {
+    let (html, tags) = crate::markdown_tags::to_html_with_tags(
+        "see {{Safe}} and {{old name}}, not {{nope}}",
+        HashMap::new(),
+        None,
+    );
+
+    assert_eq!(
+        html,
+        concat!(
+            "<div class=\"paragraph\">see <a href=\"/tags/safe\">Safe</a> and <a href=\"/tags/old+name\">old name</a>, ",
+            "not <a href=\"/tags/nope\">nope</a></div>\n"
+        )
+    );
+    assert_eq!(tags, vec!["safe", "old name", "nope"]);
+
+    let states = HashMap::from([
+        (
+            "old name".to_string(),
+            crate::markdown_tags::TagState::Aliased,
+        ),
+        ("nope".to_string(), crate::markdown_tags::TagState::Missing),
+    ]);
+    let (html, _) = crate::markdown_tags::to_html_with_tags(
+        "{{safe}} {{old name}} {{nope}}",
+        HashMap::new(),
+        Some(states),
+    );
+
+    assert_eq!(
+        html,
+        concat!(
+            "<div class=\"paragraph\"><a href=\"/tags/safe\" class=\"tag-link\">safe</a> ",
+            "<a href=\"/tags/old+name\" class=\"tag-link tag-link--aliased\">old name</a> ",
+            "<a href=\"/tags/nope\" class=\"tag-link tag-link--missing\">nope</a></div>\n"
+        )
+    );
+}
+
+fn sanitize_policy() -> crate::markdown_sanitize::SanitizePolicy {
+    crate::markdown_sanitize::SanitizePolicy {
+        tags: ["p", "a", "em", "span", "div", "script"]
+            .map(String::from)
+            .to_vec(),
+        attributes: HashMap::from([("a".to_string(), vec!["href".to_string()])]),
+        generic_attributes: vec!["class".to_string()],
+        url_schemes: vec!["https".to_string()],
+    }
+}
+
+#[test]
+fn sanitized_rendering_applies_allowlist() {
+    let html = crate::markdown_sanitize::to_html_sanitized(
+        concat!(
+            "*hi* <span class=\"x\" onclick=\"evil()\">there</span>\n\n",
+            "<a href=\"javascript:evil()\">bad</a> <a href=\"https://example.com/\">ok</a> ",
+            "<a href=\"/rules\">rules</a>\n\n",
+            "<script>evil()</script><iframe src=\"https://example.com/\"></iframe>\n\n",
+            ">>1p"
+        ),
+        HashMap::from([(
+            "1p".to_string(),
+            "<div data-image-id=\"1\">embed</div>".to_string(),
+        )]),
+        &sanitize_policy(),
+    );
+
+    assert_eq!(
+        html,
+        concat!(
+            "<div class=\"paragraph\"><em>hi</em> <span class=\"x\">there</span></div>\n",
+            "<div class=\"paragraph\"><a rel=\"nofollow ugc noopener\">bad</a> <a href=\"https://example.com/\" rel=\"nofollow ugc noopener\">ok</a> ",
+            "<a href=\"/rules\" rel=\"nofollow ugc noopener\">rules</a></div>\n",
+            "\n",
+            "<div class=\"paragraph\"><div data-image-id=\"1\">embed</div></div>\n"
+        )
+    );
+}
+
+#[test]
+fn sanitizer_checks_links_by_attribute() {
+    let policy = crate::links::LinkPolicy {
+        new_tab: false,
+        blocklist: vec!["evil.com".into()],
+    };
+    let sanitize_policy = crate::markdown_sanitize::SanitizePolicy {
+        attributes: HashMap::from([(
+            "a".to_string(),
+            vec!["href".to_string(), "title".to_string()],
+        )]),
+        ..sanitize_policy()
+    };
+
+    assert_eq!(
+        crate::markdown_sanitize::sanitize(
+            concat!(
+                "<a title=\"t\" href=\"https://evil.com/x\">a</a> ",
+                "<a title=\"t\" href=\"//evil.com/\">b</a> ",
+                "<a title=\"t\" href=\"https://example.com/\" rel=\"me\">c</a>"
+            ),
+            &sanitize_policy,
+            &policy,
+        ),
+        concat!(
+            "<a title=\"t\" rel=\"nofollow ugc noopener\">a</a> ",
+            "<a title=\"t\" rel=\"nofollow ugc noopener\">b</a> ",
+            "<a title=\"t\" href=\"https://example.com/\" rel=\"nofollow ugc noopener\">c</a>"
+        )
+    );
+}
+
+fn image_embed(
+    id: u64,
+    status: crate::markdown_embeds::ImageStatus,
+) -> crate::markdown_embeds::ImageEmbed {
+    use crate::markdown_embeds::{EmbedThumbnail, ImageEmbed};
+
+    ImageEmbed {
+        id,
+        tag_ids: vec![1, 2],
+        tag_aliases: ["safe", "sfw", "rock 'n' roll"].map(String::from).to_vec(),
+        score: -1,
+        faves: 0,
+        upvotes: 1,
+        downvotes: 2,
+        comment_count: 3,
+        created_at: "2020-01-01T00:00:00Z".to_string(),
+        sources: vec!["https://example.com/a?b=1&c=\"2\"".to_string()],
+        uris: HashMap::from([
+            ("small".to_string(), format!("/img/{id}/small.png")),
+            ("full".to_string(), format!("/img/{id}/full.png")),
+        ]),
+        width: 800,
+        height: 600,
+        aspect_ratio: 4.0 / 3.0,
+        thumbnails: HashMap::from([(
+            "small".to_string(),
+            EmbedThumbnail {
+                size: "small".to_string(),
+                url: format!("/img/{id}/small.png"),
+            },
+        )]),
+        mime_type: "image/png".to_string(),
+        title: "Size: 800x600 | Tagged: \"safe\", 'sfw'".to_string(),
+        filter_path: "/filters/1".to_string(),
+        blocked_path: "/images/tagblocked.svg".to_string(),
+        status,
+    }
+}
+
+#[test]
+fn embeds_render_by_suffix_and_status() {
+    use crate::markdown_embeds::{ImageStatus, replacements};
+
+    let embeds = vec![
+        image_embed(1, ImageStatus::Visible),
+        image_embed(2, ImageStatus::Deleted),
+        image_embed(3, ImageStatus::Pending),
+    ];
+    let reps = replacements(
+        [(1, "t"), (1, ""), (1, "p"), (2, "p"), (3, "s"), (4, "p")]
+            .map(|(id, suffix)| (id, suffix.to_string())),
+        &embeds,
+    );
+
+    assert_eq!(
+        reps["1t"],
+        concat!(
+            "<div class=\"image-show-container\" data-aspect-ratio=\"1.3333333333333333\" data-comment-count=\"3\" ",
+            "data-created-at=\"2020-01-01T00:00:00Z\" data-downvotes=\"2\" data-faves=\"0\" data-height=\"600\" ",
+            "data-image-id=\"1\" data-image-tag-aliases=\"safe, sfw, rock &#39;n&#39; roll\" data-image-tags=\"[1,2]\" ",
+            "data-score=\"-1\" data-size=\"small\" data-source-url=\"https://example.com/a?b=1&amp;c=&quot;2&quot;\" ",
+            "data-source-urls=\"[&quot;https://example.com/a?b=1&amp;c=\\&quot;2\\&quot;&quot;]\" data-tag-count=\"2\" ",
+            "data-upvotes=\"1\" data-uris=\"{&quot;full&quot;:&quot;/img/1/full.png&quot;,&quot;small&quot;:&quot;/img/1/small.png&quot;}\" ",
+            "data-width=\"800\">",
+            "<div class=\"block block--fixed block--warning block--no-margin image-filtered hidden\">",
+            "<strong><a data-click-unfilter=\"1\" href=\"#\">This image is blocked by your current filter - click here to display it anyway</a></strong>",
+            "<p><img data-click-unfilter=\"1\" height=\"250\" src=\"/images/tagblocked.svg\" width=\"250\"></p>",
+            "<span class=\"filter-explanation\"></span> <a class=\"filter-link\" href=\"/filters/1\">your current filter</a>. </div>",
+            "<div class=\"image-show hidden\"><a href=\"/images/1\" title=\"Size: 800x600 | Tagged: &quot;safe&quot;, &#39;sfw&#39;\"><span class=\"imgspoiler\">",
+            "<picture data-image-id=\"1\"><img src=\"/img/1/small.png\"></picture>",
+            "</span></a></div></div>"
+        )
+    );
+    assert_eq!(reps["1"], "<a href=\"/images/1\">&gt;&gt;1</a>");
+    assert_eq!(reps["1p"], "<a href=\"/images/1\">&gt;&gt;1p</a>");
+    assert_eq!(reps["2p"], "<a href=\"/images/2\">&gt;&gt;2p (deleted)</a>");
+    assert_eq!(reps["3s"], "&gt;&gt;3s (pending approval)");
+    assert!(!reps.contains_key("4p"));
+}
+
 fn diff(old: &str, new: &str, expected: &str) {
-    let output = crate::markdown_diff::to_html(old, new);
+    let output = crate::markdown_diff::to_html_with_options(
+        old,
+        new,
+        crate::markdown_diff::DiffMode::Unified,
+        &Default::default(),
+    );
 
     if output != expected {
         println!("Old:\n========================\n{old}\n========================");
@@ -341,7 +1451,15 @@ fn diff_collapses_unchanged_lines_after_the_context_window() {
             "<tr class=\"diff__row\"><td class=\"diff__gutter\">2</td><td class=\"diff__gutter\">2</td><td class=\"diff__text\">l2</td></tr>",
             "<tr class=\"diff__row\"><td class=\"diff__gutter\">3</td><td class=\"diff__gutter\">3</td><td class=\"diff__text\">l3</td></tr>",
             "<tr class=\"diff__row\"><td class=\"diff__gutter\">4</td><td class=\"diff__gutter\">4</td><td class=\"diff__text\">l4</td></tr>",
+            "</tbody>",
+            "<tbody class=\"diff__gap\" data-old-start=\"5\" data-new-start=\"5\" data-count=\"5\">",
             "<tr class=\"diff__row diff__row--gap\"><td class=\"diff__gutter\"></td><td class=\"diff__gutter\"></td><td class=\"diff__text\">5 unchanged lines</td></tr>",
+            "</tbody><tbody class=\"diff__hidden hidden\">",
+            "<tr class=\"diff__row\"><td class=\"diff__gutter\">5</td><td class=\"diff__gutter\">5</td><td class=\"diff__text\">l5</td></tr>",
+            "<tr class=\"diff__row\"><td class=\"diff__gutter\">6</td><td class=\"diff__gutter\">6</td><td class=\"diff__text\">l6</td></tr>",
+            "<tr class=\"diff__row\"><td class=\"diff__gutter\">7</td><td class=\"diff__gutter\">7</td><td class=\"diff__text\">l7</td></tr>",
+            "<tr class=\"diff__row\"><td class=\"diff__gutter\">8</td><td class=\"diff__gutter\">8</td><td class=\"diff__text\">l8</td></tr>",
+            "<tr class=\"diff__row\"><td class=\"diff__gutter\">9</td><td class=\"diff__gutter\">9</td><td class=\"diff__text\">l9</td></tr>",
             "</tbody></table>",
         ),
     );
@@ -353,8 +1471,16 @@ fn diff_collapses_unchanged_lines_before_the_context_window() {
         "l1\nl2\nl3\nl4\nl5\nl6\nl7\nl8\nend old",
         "l1\nl2\nl3\nl4\nl5\nl6\nl7\nl8\nend new",
         concat!(
-            "<table class=\"diff\"><tbody>",
+            "<table class=\"diff\">",
+            "<tbody class=\"diff__gap\" data-old-start=\"1\" data-new-start=\"1\" data-count=\"5\">",
             "<tr class=\"diff__row diff__row--gap\"><td class=\"diff__gutter\"></td><td class=\"diff__gutter\"></td><td class=\"diff__text\">5 unchanged lines</td></tr>",
+            "</tbody><tbody class=\"diff__hidden hidden\">",
+            "<tr class=\"diff__row\"><td class=\"diff__gutter\">1</td><td class=\"diff__gutter\">1</td><td class=\"diff__text\">l1</td></tr>",
+            "<tr class=\"diff__row\"><td class=\"diff__gutter\">2</td><td class=\"diff__gutter\">2</td><td class=\"diff__text\">l2</td></tr>",
+            "<tr class=\"diff__row\"><td class=\"diff__gutter\">3</td><td class=\"diff__gutter\">3</td><td class=\"diff__text\">l3</td></tr>",
+            "<tr class=\"diff__row\"><td class=\"diff__gutter\">4</td><td class=\"diff__gutter\">4</td><td class=\"diff__text\">l4</td></tr>",
+            "<tr class=\"diff__row\"><td class=\"diff__gutter\">5</td><td class=\"diff__gutter\">5</td><td class=\"diff__text\">l5</td></tr>",
+            "</tbody><tbody>",
             "<tr class=\"diff__row\"><td class=\"diff__gutter\">6</td><td class=\"diff__gutter\">6</td><td class=\"diff__text\">l6</td></tr>",
             "<tr class=\"diff__row\"><td class=\"diff__gutter\">7</td><td class=\"diff__gutter\">7</td><td class=\"diff__text\">l7</td></tr>",
             "<tr class=\"diff__row\"><td class=\"diff__gutter\">8</td><td class=\"diff__gutter\">8</td><td class=\"diff__text\">l8</td></tr>",
@@ -420,3 +1546,224 @@ fn diff_matches_lines_across_crlf_and_lf_revisions() {
         ),
     );
 }
+
+#[test]
+fn diff_split_mode_pairs_edited_lines() {
+    assert_eq!(
+        crate::markdown_diff::to_html_with_options(
+            "same\nold one\nold two\ngone",
+            "same\nnew one\nold two\n",
+            crate::markdown_diff::DiffMode::Split,
+            &Default::default(),
+        ),
+        concat!(
+            "<table class=\"diff diff--split\"><tbody>",
+            "<tr class=\"diff__row\"><td class=\"diff__gutter\">1</td><td class=\"diff__text\">same</td><td class=\"diff__gutter\">1</td><td class=\"diff__text\">same</td></tr>",
+            "<tr class=\"diff__row diff__row--change\"><td class=\"diff__gutter\">2</td><td class=\"diff__text diff__text--del\"><del class=\"diff__hl\">old</del> one</td><td class=\"diff__gutter\">2</td><td class=\"diff__text diff__text--ins\"><ins class=\"diff__hl\">new</ins> one</td></tr>",
+            "<tr class=\"diff__row\"><td class=\"diff__gutter\">3</td><td class=\"diff__text\">old two</td><td class=\"diff__gutter\">3</td><td class=\"diff__text\">old two</td></tr>",
+            "<tr class=\"diff__row diff__row--change\"><td class=\"diff__gutter\">4</td><td class=\"diff__text diff__text--del\">gone</td><td class=\"diff__gutter\"></td><td class=\"diff__text diff__text--empty\"></td></tr>",
+            "</tbody></table>",
+        ),
+    );
+}
+
+#[test]
+fn diff_split_mode_collapses_gaps() {
+    let html = crate::markdown_diff::to_html_with_options(
+        "start old\nl2\nl3\nl4\nl5\nl6\nl7\nl8\nl9",
+        "start new\nl2\nl3\nl4\nl5\nl6\nl7\nl8\nl9",
+        crate::markdown_diff::DiffMode::Split,
+        &Default::default(),
+    );
+
+    assert!(html.ends_with(concat!(
+        "</tbody><tbody class=\"diff__gap\" data-old-start=\"5\" data-new-start=\"5\" data-count=\"5\">",
+        "<tr class=\"diff__row diff__row--gap\"><td class=\"diff__gutter\"></td><td class=\"diff__text\" colspan=\"3\">5 unchanged lines</td></tr>",
+        "</tbody><tbody class=\"diff__hidden hidden\">",
+        "<tr class=\"diff__row\"><td class=\"diff__gutter\">5</td><td class=\"diff__text\">l5</td><td class=\"diff__gutter\">5</td><td class=\"diff__text\">l5</td></tr>",
+        "<tr class=\"diff__row\"><td class=\"diff__gutter\">6</td><td class=\"diff__text\">l6</td><td class=\"diff__gutter\">6</td><td class=\"diff__text\">l6</td></tr>",
+        "<tr class=\"diff__row\"><td class=\"diff__gutter\">7</td><td class=\"diff__text\">l7</td><td class=\"diff__gutter\">7</td><td class=\"diff__text\">l7</td></tr>",
+        "<tr class=\"diff__row\"><td class=\"diff__gutter\">8</td><td class=\"diff__text\">l8</td><td class=\"diff__gutter\">8</td><td class=\"diff__text\">l8</td></tr>",
+        "<tr class=\"diff__row\"><td class=\"diff__gutter\">9</td><td class=\"diff__text\">l9</td><td class=\"diff__gutter\">9</td><td class=\"diff__text\">l9</td></tr>",
+        "</tbody></table>",
+    )));
+}
+
+#[test]
+fn diff_rendered_mode_marks_changed_blocks() {
+    assert_eq!(
+        crate::markdown_diff::to_html_with_options(
+            "Same\n\n[link](/old)\n\n<b>gone</b>",
+            "Same\r\n\r\n[link](/new)\r\n\r\n| a |\r\n|---|\r\n| b |",
+            crate::markdown_diff::DiffMode::Rendered,
+            &Default::default(),
+        ),
+        concat!(
+            "<div class=\"diff diff--rendered\">",
+            "<div class=\"paragraph\">Same</div>\n",
+            "<del class=\"diff__block\"><div class=\"paragraph\"><a href=\"/old\">link</a></div>\n</del>",
+            "<del class=\"diff__block\"><div class=\"paragraph\">&lt;b&gt;gone&lt;/b&gt;</div>\n</del>",
+            "<ins class=\"diff__block\"><div class=\"paragraph\"><a href=\"/new\">link</a></div>\n</ins>",
+            "<ins class=\"diff__block\"><table>\n<thead>\n<tr>\n<th>a</th>\n</tr>\n</thead>\n<tbody>\n<tr>\n<td>b</td>\n</tr>\n</tbody>\n</table>\n</ins>",
+            "</div>",
+        ),
+    );
+}
+
+#[test]
+fn merge_applies_both_sides() {
+    assert_eq!(
+        crate::markdown_diff::merge(
+            "one\ntwo\nthree\nfour\nfive",
+            "one\r\ntwo\r\nthree\r\nfour\r\nFIVE\r\n",
+            "ONE\nzero\ntwo\nthree\nfour\nfive\n",
+        ),
+        Ok("ONE\nzero\ntwo\nthree\nfour\nFIVE\n".to_string()),
+    );
+    assert_eq!(
+        crate::markdown_diff::merge("a\nb\nc\n", "a\nB\nc\n", "a\nB\nc\n"),
+        Ok("a\nB\nc\n".to_string()),
+    );
+}
+
+#[test]
+fn merge_reports_conflicts() {
+    assert_eq!(
+        crate::markdown_diff::merge(
+            "one\ntwo\nthree\nfour\nfive\n",
+            "one\nTWO\nthree\nfour\nfive\n",
+            "one\ndeux\nthree\nfour\nfive\nsix\n",
+        ),
+        Err(vec![crate::markdown_diff::Conflict {
+            base: (1, 2),
+            ours: (1, 2),
+            theirs: (1, 2),
+            base_text: "two\n".to_string(),
+            ours_text: "TWO\n".to_string(),
+            theirs_text: "deux\n".to_string(),
+        }]),
+    );
+}
+
+#[test]
+fn diff_hunks_match_grouping() {
+    use crate::markdown_diff::{DiffHunk, HunkOp};
+
+    let hunks = crate::markdown_diff::hunks(
+        "a\nb\nc\nd\ne\nThe quick fox",
+        "a\r\nb\r\nc\r\nd\r\ne\r\nThe quick brown fox\r\n",
+        &Default::default(),
+    );
+
+    assert_eq!(
+        hunks[0],
+        DiffHunk {
+            op: HunkOp::Equal,
+            old_range: (2, 5),
+            new_range: (2, 5),
+            lines: vec!["c".into(), "d".into(), "e".into()],
+            inline_spans: vec![vec![], vec![], vec![]],
+        },
+    );
+    assert_eq!(hunks.len(), 2);
+    assert_eq!(hunks[1].op, HunkOp::Replace);
+    assert_eq!((hunks[1].old_range, hunks[1].new_range), ((5, 6), (5, 6)));
+    assert_eq!(hunks[1].lines, ["The quick fox", "The quick brown fox"]);
+    assert!(hunks[1].inline_spans[0].is_empty());
+
+    let inserted: String = hunks[1].inline_spans[1]
+        .iter()
+        .map(|&(start, end)| &hunks[1].lines[1][start..end])
+        .collect();
+    assert_eq!(inserted.trim(), "brown");
+
+    assert!(crate::markdown_diff::hunks("same", "same\r\n", &Default::default()).is_empty());
+}
+
+#[test]
+fn diff_stats_count_changed_characters() {
+    assert_eq!(
+        crate::markdown_diff::stats(
+            "The quick fox\nkeep\ngone line",
+            "The quick brown fox\r\nkeep\r\nnew\r\n",
+            &Default::default(),
+        ),
+        crate::markdown_diff::DiffStats {
+            lines_added: 2,
+            lines_removed: 2,
+            chars_added: 7,
+            chars_removed: 7,
+        },
+    );
+}
+
+#[test]
+fn diff_stats_count_whole_lines_once_out_of_time() {
+    use crate::markdown_diff::{DiffOptions, DiffStats, stats};
+
+    let options = DiffOptions {
+        timeout_ms: 0,
+        ..Default::default()
+    };
+
+    assert_eq!(
+        stats("a red fox", "a tan fox", &Default::default()),
+        DiffStats {
+            lines_added: 1,
+            lines_removed: 1,
+            chars_added: 3,
+            chars_removed: 3,
+        },
+    );
+    assert_eq!(
+        stats("a red fox", "a tan fox", &options),
+        DiffStats {
+            lines_added: 1,
+            lines_removed: 1,
+            chars_added: 9,
+            chars_removed: 9,
+        },
+    );
+}
+
+#[test]
+fn diff_context_and_algorithm_are_configurable() {
+    use crate::markdown_diff::{DiffAlgorithm, DiffMode, DiffOptions};
+
+    let html = crate::markdown_diff::to_html_with_options(
+        "l1\nl2\nl3\nl4 old\nl5\nl6\nl7",
+        "l1\nl2\nl3\nl4 new\nl5\nl6\nl7\nl8",
+        DiffMode::Unified,
+        &DiffOptions {
+            context_lines: 1,
+            algorithm: DiffAlgorithm::Myers,
+            timeout_ms: 1000,
+        },
+    );
+
+    assert_eq!(
+        html,
+        concat!(
+            "<table class=\"diff\">",
+            "<tbody class=\"diff__gap\" data-old-start=\"1\" data-new-start=\"1\" data-count=\"2\">",
+            "<tr class=\"diff__row diff__row--gap\"><td class=\"diff__gutter\"></td><td class=\"diff__gutter\"></td><td class=\"diff__text\">2 unchanged lines</td></tr>",
+            "</tbody><tbody class=\"diff__hidden hidden\">",
+            "<tr class=\"diff__row\"><td class=\"diff__gutter\">1</td><td class=\"diff__gutter\">1</td><td class=\"diff__text\">l1</td></tr>",
+            "<tr class=\"diff__row\"><td class=\"diff__gutter\">2</td><td class=\"diff__gutter\">2</td><td class=\"diff__text\">l2</td></tr>",
+            "</tbody><tbody>",
+            "<tr class=\"diff__row\"><td class=\"diff__gutter\">3</td><td class=\"diff__gutter\">3</td><td class=\"diff__text\">l3</td></tr>",
+            "<tr class=\"diff__row diff__row--del\"><td class=\"diff__gutter\">4</td><td class=\"diff__gutter\"></td><td class=\"diff__text\">l4 <del class=\"diff__hl\">old</del></td></tr>",
+            "<tr class=\"diff__row diff__row--ins\"><td class=\"diff__gutter\"></td><td class=\"diff__gutter\">4</td><td class=\"diff__text\">l4 <ins class=\"diff__hl\">new</ins></td></tr>",
+            "<tr class=\"diff__row\"><td class=\"diff__gutter\">5</td><td class=\"diff__gutter\">5</td><td class=\"diff__text\">l5</td></tr>",
+            "</tbody>",
+            "<tbody class=\"diff__gap\" data-old-start=\"6\" data-new-start=\"6\" data-count=\"1\">",
+            "<tr class=\"diff__row diff__row--gap\"><td class=\"diff__gutter\"></td><td class=\"diff__gutter\"></td><td class=\"diff__text\">1 unchanged line</td></tr>",
+            "</tbody><tbody class=\"diff__hidden hidden\">",
+            "<tr class=\"diff__row\"><td class=\"diff__gutter\">6</td><td class=\"diff__gutter\">6</td><td class=\"diff__text\">l6</td></tr>",
+            "</tbody><tbody>",
+            "<tr class=\"diff__row\"><td class=\"diff__gutter\">7</td><td class=\"diff__gutter\">7</td><td class=\"diff__text\">l7</td></tr>",
+            "<tr class=\"diff__row diff__row--ins\"><td class=\"diff__gutter\"></td><td class=\"diff__gutter\">8</td><td class=\"diff__text\">l8</td></tr>",
+            "</tbody></table>",
+        ),
+    );
+}
@@ -0,0 +1,545 @@
+// Conversion of legacy Textile to Markdown.
+//
+// Before Markdown, posts and descriptions were written in a Textile dialect:
+// `*bold*`, `_italic_`, `+underline+`, `-strike-`, `^sup^`, `~sub~`,
+// `@code@`, `??cite??`, `[spoiler]...[/spoiler]`, `[bq]...[/bq]` quotes,
+// `==literal==` and `[==literal==]` escapes, `"text":url` links and
+// `!url!` / `!url!:link` images. This converter rewrites those forms into
+// Markdown which renders the same way under `markdown::common_options()`,
+// and escapes everything else so that text which was literal in Textile
+// stays literal in Markdown. Constructs which cannot be converted cleanly
+// (such as unbalanced spoiler or quote tags) are reported with their line
+// number so a migration can flag the post for review.
+//
+// Spans, spoilers and link labels are converted by recursing into their
+// contents. Markup nested more than `MAX_DEPTH` levels deep is kept as
+// literal text and reported instead.
+
+use regex::Regex;
+use rustler::NifMap;
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+static LINK: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"^"([^"\n]+)":([^\s<>"]*[^\s<>".,;:!?)])"#).unwrap());
+static IMAGE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"^![<>]?([^\s!()]+)(?:\([^)\n]*\))?!(?::([^\s<>"]*[^\s<>".,;:!?)]))?"#).unwrap()
+});
+static BARE_URL: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"^https?://[^\s<>"]*[^\s<>".,;:!?)]"#).unwrap());
+static QUOTE_TAG: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"^\[bq(?:="[^"\]]*")?\]"#).unwrap());
+
+/// Simple span delimiters and the Markdown they become.
+const SPANS: &[(&str, &str)] = &[
+    ("??", "*"),
+    ("*", "**"),
+    ("_", "*"),
+    ("+", "__"),
+    ("-", "~~"),
+    ("^", "^"),
+    ("~", "~"),
+];
+
+/// Deepest nesting of inline markup which is converted.
+const MAX_DEPTH: usize = 32;
+
+/// Characters which are always escaped in literal text.
+const ESCAPED: &[char] = &['\\', '*', '_', '[', ']', '(', ')', '^', '`', '~', '|', '<'];
+
+/// Characters which are escaped at the start of a line, where they would
+/// otherwise begin a list, heading, table or quote. A `>` is left alone when
+/// it begins an image mention (`>>1234`), which Textile rendered too.
+const ESCAPED_AT_LINE_START: &[char] = &['#', '-', '+', '=', '>'];
+
+/// A construct which could not be converted and was kept as literal text.
+#[derive(NifMap, Clone, Debug, PartialEq, Eq)]
+pub struct Unconverted {
+    pub line: usize,
+    pub text: String,
+    pub reason: String,
+}
+
+#[derive(NifMap, Clone, Debug, PartialEq, Eq)]
+pub struct Conversion {
+    pub markdown: String,
+    pub unconverted: Vec<Unconverted>,
+}
+
+/// Convert a Textile document to Markdown.
+pub fn to_markdown(input: &str) -> Conversion {
+    let input = input.replace("\r\n", "\n");
+    let mut converter = Converter {
+        source: &input,
+        unconverted: vec![],
+    };
+
+    let mut writer = QuoteWriter::default();
+    converter.quotes(&mut writer);
+
+    Conversion {
+        markdown: writer.finish(),
+        unconverted: converter.unconverted,
+    }
+}
+
+struct Converter<'s> {
+    source: &'s str,
+    unconverted: Vec<Unconverted>,
+}
+
+impl<'s> Converter<'s> {
+    fn report(&mut self, offset: usize, text: &str, reason: &str) {
+        self.unconverted.push(Unconverted {
+            line: self.source[..offset].matches('\n').count() + 1,
+            text: text.into(),
+            reason: reason.into(),
+        });
+    }
+
+    /// Split the document on `[bq]` / `[/bq]` tags and convert each run of
+    /// text at its quote depth.
+    fn quotes(&mut self, writer: &mut QuoteWriter) {
+        let source = self.source;
+        let mut depth = 0;
+        let mut open_offsets = vec![];
+        let mut start = 0;
+        let mut i = 0;
+
+        while i < source.len() {
+            let rest = &source[i..];
+
+            if let Some(tag) = QUOTE_TAG.find(rest) {
+                writer.push(depth, &self.inline(start, &source[start..i], 0));
+                open_offsets.push(i);
+                depth += 1;
+                i += tag.end();
+                start = i;
+            } else if rest.starts_with("[/bq]") {
+                if depth == 0 {
+                    self.report(i, "[/bq]", "quote closed without being opened");
+                    i += "[/bq]".len();
+                    continue;
+                }
+
+                writer.push(depth, &self.inline(start, &source[start..i], 0));
+                open_offsets.pop();
+                depth -= 1;
+                i += "[/bq]".len();
+                start = i;
+            } else {
+                i += rest.chars().next().map_or(1, char::len_utf8);
+            }
+        }
+
+        writer.push(depth, &self.inline(start, &source[start..], 0));
+
+        for offset in open_offsets {
+            self.report(offset, "[bq]", "quote is never closed");
+        }
+    }
+
+    /// Convert inline Textile in `text`, which starts at `base` in the
+    /// source and is nested `depth` levels inside other markup.
+    fn inline(&mut self, base: usize, text: &str, depth: usize) -> String {
+        let mut out = String::with_capacity(text.len());
+
+        if depth > MAX_DEPTH {
+            self.report(base, text, "markup is nested too deeply to convert");
+            escape_into(&mut out, text, "");
+            return out;
+        }
+
+        let spoilers = matching_tags(text, "[spoiler]", "[/spoiler]");
+        let span_ends = SpanEnds::new(text);
+        let mut line_start = 0;
+        let mut scanned = 0;
+        let mut i = 0;
+
+        while i < text.len() {
+            let rest = &text[i..];
+
+            if let Some(n) = text[scanned..i].rfind('\n') {
+                line_start = scanned + n + 1;
+            }
+            scanned = i;
+
+            let line = &text[line_start..i];
+
+            // [==literal==] and ==literal==
+            if let Some((inner, len)) = literal(text, i, &span_ends) {
+                escape_into(&mut out, inner, line);
+                i += len;
+                continue;
+            }
+
+            if rest.starts_with("[spoiler]") {
+                match spoilers.get(&i).map(|close| close - i) {
+                    Some(end) => {
+                        let inner = &rest["[spoiler]".len()..end];
+                        let inner = self.inline(base + i + "[spoiler]".len(), inner, depth + 1);
+                        spoiler_into(&mut out, &inner);
+                        i += end + "[/spoiler]".len();
+                    }
+                    None => {
+                        self.report(base + i, "[spoiler]", "spoiler is never closed");
+                        escape_into(&mut out, "[spoiler]", line);
+                        i += "[spoiler]".len();
+                    }
+                }
+                continue;
+            }
+
+            if rest.starts_with("[/spoiler]") {
+                self.report(
+                    base + i,
+                    "[/spoiler]",
+                    "spoiler closed without being opened",
+                );
+                escape_into(&mut out, "[/spoiler]", line);
+                i += "[/spoiler]".len();
+                continue;
+            }
+
+            if let Some(caps) = LINK.captures(rest) {
+                let label = self.inline(base + i + 1, &caps[1], depth + 1);
+                out.push('[');
+                out.push_str(&label);
+                out.push_str("](");
+                push_url(&mut out, &caps[2]);
+                out.push(')');
+                i += caps[0].len();
+                continue;
+            }
+
+            if let Some(caps) = IMAGE.captures(rest) {
+                let image = format!("![]({})", url_for(&caps[1]));
+
+                match caps.get(2) {
+                    Some(link) => {
+                        out.push('[');
+                        out.push_str(&image);
+                        out.push_str("](");
+                        push_url(&mut out, link.as_str());
+                        out.push(')');
+                    }
+                    None => out.push_str(&image),
+                }
+                i += caps[0].len();
+                continue;
+            }
+
+            if word_start(text, i)
+                && let Some(url) = BARE_URL.find(rest)
+            {
+                out.push_str(url.as_str());
+                i += url.end();
+                continue;
+            }
+
+            if rest.starts_with('@')
+                && let Some(end) = span_ends.find(text, i, "@")
+            {
+                code_into(&mut out, &text[i + 1..end]);
+                i = end + 1;
+                continue;
+            }
+
+            if let Some((open, md, end)) = SPANS
+                .iter()
+                .filter(|(open, _)| rest.starts_with(open))
+                .find_map(|(open, md)| span_ends.find(text, i, open).map(|end| (open, md, end)))
+            {
+                let inner =
+                    self.inline(base + i + open.len(), &text[i + open.len()..end], depth + 1);
+                out.push_str(md);
+                out.push_str(&inner);
+                out.push_str(md);
+                i = end + open.len();
+                continue;
+            }
+
+            let ch = rest.chars().next().unwrap();
+            escape_char_into(&mut out, ch, line, &rest[ch.len_utf8()..]);
+            i += ch.len_utf8();
+        }
+
+        out
+    }
+}
+
+/// Accumulates converted text, prefixing lines with `> ` markers for their
+/// quote depth and separating blocks of different depth with blank lines.
+#[derive(Default)]
+struct QuoteWriter {
+    out: String,
+    depth: usize,
+}
+
+impl QuoteWriter {
+    fn push(&mut self, depth: usize, text: &str) {
+        let text = if depth == self.depth {
+            text
+        } else {
+            text.trim_matches('\n')
+        };
+
+        if text.is_empty() {
+            return;
+        }
+
+        if depth != self.depth && !self.out.is_empty() {
+            let trimmed = self.out.trim_end_matches('\n').len();
+            self.out.truncate(trimmed);
+            self.out.push('\n');
+            self.out.push_str(&prefix(self.depth.min(depth)));
+            self.out.push('\n');
+        }
+
+        let at_line_start = self.out.is_empty() || self.out.ends_with('\n');
+
+        for (n, line) in text.split('\n').enumerate() {
+            if n > 0 {
+                self.out.push('\n');
+            }
+            if n > 0 || at_line_start {
+                self.out.push_str(&prefix(depth));
+            }
+            self.out.push_str(line);
+        }
+
+        self.depth = depth;
+    }
+
+    fn finish(self) -> String {
+        self.out
+            .lines()
+            .map(str::trim_end)
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+fn prefix(depth: usize) -> String {
+    "> ".repeat(depth)
+}
+
+/// A `[==...==]` or `==...==` literal at `i`, as its contents and total
+/// length.
+fn literal<'t>(text: &'t str, i: usize, span_ends: &SpanEnds) -> Option<(&'t str, usize)> {
+    let rest = &text[i..];
+
+    if let Some(inner) = rest.strip_prefix("[==") {
+        let end = inner.find("==]")?;
+        return Some((&inner[..end], end + "[====]".len()));
+    }
+
+    if rest.starts_with("==") && word_start(text, i) {
+        let end = span_ends.find(text, i, "==")?;
+        return Some((&text[i + 2..end], end + 2 - i));
+    }
+
+    None
+}
+
+/// Offsets of the `close` tags in `text` keyed by the offset of the `open`
+/// tag each one matches, allowing nesting. Found in a single pass, so that
+/// looking up each opening tag does not rescan the rest of the text.
+fn matching_tags(text: &str, open: &str, close: &str) -> HashMap<usize, usize> {
+    let mut pairs = HashMap::new();
+    let mut opened = vec![];
+    let mut i = 0;
+
+    while i < text.len() {
+        let rest = &text[i..];
+
+        if rest.starts_with(open) {
+            opened.push(i);
+            i += open.len();
+        } else if rest.starts_with(close) {
+            if let Some(start) = opened.pop() {
+                pairs.insert(start, i);
+            }
+            i += close.len();
+        } else {
+            i += rest.chars().next().map_or(1, char::len_utf8);
+        }
+    }
+
+    pairs
+}
+
+/// Whether position `i` begins a word: the previous character is missing,
+/// whitespace or punctuation.
+fn word_start(text: &str, i: usize) -> bool {
+    text[..i]
+        .chars()
+        .next_back()
+        .is_none_or(|c| !c.is_alphanumeric())
+}
+
+/// Where spans in a run of text can end: every delimiter which could close
+/// a span, and every blank line, found in one pass per delimiter so that
+/// looking for the end of each opener (most of which never close) does not
+/// rescan the rest of the paragraph.
+struct SpanEnds {
+    closers: HashMap<&'static str, Vec<usize>>,
+    blank_lines: Vec<usize>,
+}
+
+impl SpanEnds {
+    fn new(text: &str) -> Self {
+        let closers = SPANS
+            .iter()
+            .map(|(open, _)| *open)
+            .chain(["@", "=="])
+            .map(|delim| {
+                let ends = text
+                    .match_indices(delim)
+                    .map(|(end, _)| end)
+                    .filter(|&end| closes_span(text, end, delim))
+                    .collect();
+
+                (delim, ends)
+            })
+            .collect();
+
+        SpanEnds {
+            closers,
+            blank_lines: text.match_indices("\n\n").map(|(at, _)| at).collect(),
+        }
+    }
+
+    /// Offset of the delimiter closing a span opened by `delim` at `i`,
+    /// using Textile's rules: the opener follows a word boundary and
+    /// precedes a non-space, the closer follows a non-space and precedes a
+    /// word boundary, and the span does not cross a blank line.
+    fn find(&self, text: &str, i: usize, delim: &str) -> Option<usize> {
+        if !word_start(text, i) {
+            return None;
+        }
+
+        let body_start = i + delim.len();
+        let first = text[body_start..]
+            .chars()
+            .next()
+            .filter(|c| !c.is_whitespace())?;
+        let limit = first_from(&self.blank_lines, body_start).unwrap_or(text.len());
+
+        first_from(self.closers.get(delim)?, body_start + first.len_utf8())
+            .filter(|end| end + delim.len() <= limit)
+    }
+}
+
+/// The first of the sorted `offsets` at or after `from`.
+fn first_from(offsets: &[usize], from: usize) -> Option<usize> {
+    offsets
+        .get(offsets.partition_point(|&at| at < from))
+        .copied()
+}
+
+/// Whether `delim` at `end` can close a span: it follows a non-space and
+/// precedes a word boundary.
+fn closes_span(text: &str, end: usize, delim: &str) -> bool {
+    let before = text[..end].chars().next_back();
+    let after = text[end + delim.len()..].chars().next();
+
+    before.is_some_and(|c| !c.is_whitespace()) && after.is_none_or(|c| !c.is_alphanumeric())
+}
+
+/// Escape `ch`, which follows `line` on its line and is followed by `after`,
+/// if Markdown would give it meaning that Textile does not.
+fn escape_char_into(out: &mut String, ch: char, line: &str, after: &str) {
+    let indent = line.trim_start_matches(' ');
+    let block_start = indent.is_empty()
+        && ESCAPED_AT_LINE_START.contains(&ch)
+        && !(ch == '>' && is_mention(after));
+    let list_number = matches!(ch, '.' | ')')
+        && !indent.is_empty()
+        && indent.len() <= 9
+        && indent.chars().all(|c| c.is_ascii_digit());
+
+    if ESCAPED.contains(&ch) || block_start || list_number {
+        out.push('\\');
+    }
+    out.push(ch);
+}
+
+/// Whether `text`, which follows a `>`, completes an image mention.
+fn is_mention(text: &str) -> bool {
+    text.strip_prefix('>')
+        .and_then(|rest| rest.chars().next())
+        .is_some_and(|c| c.is_ascii_digit())
+}
+
+fn escape_into(out: &mut String, text: &str, line: &str) {
+    let mut line = line.to_string();
+
+    for (i, ch) in text.char_indices() {
+        escape_char_into(out, ch, &line, &text[i + ch.len_utf8()..]);
+
+        if ch == '\n' {
+            line.clear();
+        } else {
+            line.push(ch);
+        }
+    }
+}
+
+/// Wrap each paragraph of `inner` in a spoiler; Markdown spoilers cannot
+/// span paragraphs.
+fn spoiler_into(out: &mut String, inner: &str) {
+    let paragraphs: Vec<&str> = inner
+        .split("\n\n")
+        .map(|p| p.trim_matches('\n'))
+        .filter(|p| !p.trim().is_empty())
+        .collect();
+
+    for (n, paragraph) in paragraphs.iter().enumerate() {
+        if n > 0 {
+            out.push_str("\n\n");
+        }
+        out.push_str("||");
+        out.push_str(paragraph);
+        out.push_str("||");
+    }
+}
+
+/// Emit `code` as a code span, using a backtick fence longer than any run of
+/// backticks inside it.
+fn code_into(out: &mut String, code: &str) {
+    let longest = code.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    let fence = "`".repeat(longest + 1);
+    let pad = if code.starts_with('`') || code.ends_with('`') {
+        " "
+    } else {
+        ""
+    };
+
+    out.push_str(&fence);
+    out.push_str(pad);
+    out.push_str(code);
+    out.push_str(pad);
+    out.push_str(&fence);
+}
+
+/// Textile image and link targets are bare; Markdown needs parentheses and
+/// spaces escaped.
+fn url_for(url: &str) -> String {
+    let mut out = String::with_capacity(url.len());
+
+    for ch in url.chars() {
+        match ch {
+            '(' => out.push_str("%28"),
+            ')' => out.push_str("%29"),
+            ' ' => out.push_str("%20"),
+            _ => out.push(ch),
+        }
+    }
+
+    out
+}
+
+fn push_url(out: &mut String, url: &str) {
+    out.push_str(&url_for(url));
+}
@@ -0,0 +1,84 @@
+defmodule PhilomenaWeb.MarkdownRendererTest do
+  use PhilomenaWeb.ConnCase, async: true
+
+  import Philomena.ImagesFixtures
+
+  alias Philomena.Repo
+  alias PhilomenaWeb.ImageView
+  alias PhilomenaWeb.MarkdownRenderer
+
+  setup %{conn: conn} do
+    # Run the browser pipeline for the assigns the template reads
+    # (current_user, current_filter, ...) without dispatching to a page.
+    conn =
+      conn
+      |> bypass_through(PhilomenaWeb.Router, [:browser])
+      |> get(~p"/")
+
+    image =
+      %{
+        image_width: 1920,
+        image_height: 1080,
+        image_aspect_ratio: 1920 / 1080,
+        tags: "safe, rock 'n' roll",
+        sources: ["https://example.com/a?b=1&c=2"]
+      }
+      |> image_fixture()
+      |> Repo.preload([:sources, tags: :aliases], force: true)
+
+    %{conn: conn, image: image}
+  end
+
+  for {suffix, version} <- [{"p", :medium}, {"t", :small}, {"s", :thumb_small}] do
+    test ">>ID#{suffix} embeds the image as the image target template renders it",
+         %{conn: conn, image: image} do
+      expected =
+        Phoenix.View.render_to_string(ImageView, "_image_target.html",
+          embed_display: true,
+          image: image,
+          size: ImageView.select_version(image, unquote(version)),
+          conn: conn
+        )
+
+      rendered =
+        %{body: ">>#{image.id}#{unquote(suffix)}"}
+        |> MarkdownRenderer.render_one(conn)
+        |> Phoenix.HTML.safe_to_string()
+
+      assert expected =~ "data-image-tags"
+      assert expected =~ "filter-explanation"
+      assert normalize(rendered) =~ normalize(expected)
+    end
+  end
+
+  test "mentions of deleted images link to the image", %{conn: conn, image: image} do
+    image = Repo.update!(Ecto.Changeset.change(image, hidden_from_users: true))
+
+    rendered =
+      %{body: ">>#{image.id}p"}
+      |> MarkdownRenderer.render_one(conn)
+      |> Phoenix.HTML.safe_to_string()
+
+    assert rendered =~ ~s(<a href="/images/#{image.id}">&gt;&gt;#{image.id}p (deleted)</a>)
+    refute rendered =~ "image-show-container"
+  end
+
+  # Attribute order and whitespace between tags depend on the template
+  # engine, so compare tags with sorted attributes and no surrounding space.
+  defp normalize(html) do
+    html =
+      Regex.replace(~r/<([a-z]+)((?:\s+[a-z-]+(?:="[^"]*")?)*)\s*>/, html, fn _, tag, attrs ->
+        attrs =
+          ~r/[a-z-]+(?:="[^"]*")?/
+          |> Regex.scan(attrs)
+          |> List.flatten()
+          |> Enum.sort()
+
+        "<" <> Enum.join([tag | attrs], " ") <> ">"
+      end)
+
+    html
+    |> String.replace(~r/\s*</, "<")
+    |> String.replace(~r/>\s*/, ">")
+  end
+end